@@ -5,7 +5,13 @@ MEMORY {
      * has, but your board may have more or less Flash and you should adjust
      * this value to suit.
      */
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 1024K - 0x100
+
+    /*
+     * Second half of the flash holds application images, loaded at boot.
+     * They can be flashed without touching the kernel.
+     */
+    APPS : ORIGIN = 0x10100000, LENGTH = 1024K
 
     /* Ram size */ 
 
@@ -90,6 +96,24 @@ SECTIONS {
     } > FLASH
 } INSERT AFTER .text;
 
+/*
+ * Kernel heap, carved out of RAM right after the statics.
+ * The MSP stack grows down from the top of RAM towards it.
+ */
+_kernel_heap_size = 4K;
+
+SECTIONS {
+    .kernel_heap (NOLOAD) : ALIGN(8)
+    {
+        _kernel_heap_start = .;
+        . += _kernel_heap_size;
+        _kernel_heap_end = .;
+    } > RAM
+} INSERT AFTER .uninit;
+
+/* Stack must not run into the heap, cortex-m-rt checks this for us */
+_stack_end = _kernel_heap_end;
+
 SECTIONS {
     .flash_end : {
         __flash_binary_end = .;
@@ -103,8 +127,16 @@ _kernel_data_size = LENGTH(RAM);
 _wifi_start = ORIGIN(WIFI);
 _wifi_size = LENGTH(WIFI);
 
+_apps_start = ORIGIN(APPS);
+_apps_size = LENGTH(APPS);
+
 _processes_start = ORIGIN(PROCESSES);
 _processes_size = LENGTH(PROCESSES);
 
+_core0_stack_start = ORIGIN(CORE0_STACK);
+_core0_stack_size = LENGTH(CORE0_STACK);
+_core1_stack_start = ORIGIN(CORE1_STACK);
+_core1_stack_size = LENGTH(CORE1_STACK);
+
 _core0_stack_top = ORIGIN(CORE0_STACK) + LENGTH(CORE0_STACK);
 _core1_stack_top = ORIGIN(CORE1_STACK) + LENGTH(CORE1_STACK);
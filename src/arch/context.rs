@@ -1,4 +1,4 @@
-use crate::{check_sleep_and_wake, Scheduler, CURRENT, PCB, PROCS, SCHEDULER, SLEEP_QUEUE};
+use crate::{check_sleep_and_wake, create_idle_process, deliver_signal, restore_signal_frame, terminate, Scheduler, CURRENT, HANDOFF, IDLE, PCB, PROCS, SCHEDULER, SLEEP_QUEUE};
 use core::ptr;
 
 #[unsafe(no_mangle)]
@@ -17,29 +17,42 @@ extern "C" fn get_new_sp() -> *const u32 {
             }
         }
 
-        // Get new process
-        let next_pid = (*sched).dequeue().ok().unwrap();
-
         let old_pcb: *mut PCB = PROCS[old_pid as usize].as_mut().unwrap();
+        (*old_pcb).sp = psp;
+
+        // Back from a signal handler, resume what it interrupted
+        restore_signal_frame(&mut *old_pcb);
+
+        // Requeue old process before picking, so a lone process keeps running
         match (*old_pcb).state {
-            crate::ProcessState::Ready | crate::ProcessState::Running => {
+            crate::ProcessState::Ready | crate::ProcessState::Running
+                if IDLE != Some(old_pid) => {
+                (*old_pcb).state = crate::ProcessState::Ready;
                 let _ = (*sched).enqueue(old_pid);
             }
+            // Context is saved, the supervisor may now rebuild the stack
+            crate::ProcessState::Zombie(reason) => terminate(old_pid, reason),
             _ => {},
         }
 
-        (*old_pcb).sp = psp;
-        
-        if old_pid == next_pid {
-            return (*old_pcb).sp as *const u32;
-        }
+        // Direct switch if one was asked for, else the head of the run queue,
+        // falling back to idle when nothing is ready
+        let handoff = HANDOFF;
+        HANDOFF = None;
+        let next_pid = match handoff {
+            Some(pid) if matches!(PROCS[pid as usize].as_ref().map(|p| p.state),
+                Some(crate::ProcessState::Ready)) => {
+                (*sched).remove(pid);
+                pid
+            }
+            _ => (*sched).dequeue().ok().or(IDLE).unwrap(),
+        };
         
         CURRENT = Some(next_pid);
         
         let new_pcb: *mut PCB = PROCS[next_pid as usize].as_mut().unwrap();
-        
-        (*old_pcb).state = crate::ProcessState::Ready;
         (*new_pcb).state = crate::ProcessState::Running;
+        deliver_signal(&mut *new_pcb);
 
         return (*new_pcb).sp as *const u32;
     }
@@ -51,10 +64,12 @@ extern "C" fn get_new_sp() -> *const u32 {
  * */
 pub fn start_first_process() -> () {
     let sched = core::ptr::addr_of_mut!(SCHEDULER); 
+    create_idle_process().unwrap();
     unsafe {
-        let pid = (*sched).dequeue().unwrap();
+        let pid = (*sched).dequeue().ok().or(IDLE).unwrap();
         let process = PROCS[pid as usize].unwrap();
         CURRENT = Some(pid);
+        PROCS[pid as usize].as_mut().unwrap().state = crate::ProcessState::Running;
 
         // This function should not return 
         run_first_process(process.sp);
@@ -0,0 +1,51 @@
+use crate::{ExitReason, ProcessState, CURRENT, PROCS};
+
+/*
+ * Mark the running process as faulted. Its context is saved by PendSV
+ * right after, and get_new_sp hands it over to the supervisor
+ * */
+#[unsafe(no_mangle)]
+extern "C" fn fault_current() {
+    unsafe {
+        if let Some(pcb) = CURRENT.and_then(|pid| PROCS[pid as usize].as_mut()) {
+            pcb.state = ProcessState::Zombie(ExitReason::Faulted);
+        }
+    }
+}
+
+/*
+ * Fault raised by kernel code, nothing sane to return to
+ * */
+#[unsafe(no_mangle)]
+extern "C" fn kernel_fault() -> ! {
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Overrides the cortex-m-rt HardFault. A fault from thread mode (PSP)
+/// only kills the process, so we reuse PendSV to switch away from it.
+/// A fault from handler mode is a kernel bug.
+///
+/// # Safety
+/// Exception entry only, never call directly.
+#[unsafe(no_mangle)]
+#[unsafe(naked)]
+pub unsafe extern "C" fn HardFault() {
+    core::arch::naked_asm!(
+        // EXC_RETURN bit 2 set means the process stack was in use
+        "mov r0, lr",
+        "movs r1, #4",
+        "tst r0, r1",
+        "beq 1f",
+
+        // r4-r11 are callee saved, still untouched for PendSV to store
+        "bl fault_current",
+        "ldr r0, =PendSV",
+        "bx r0",
+
+        "1:",
+        "ldr r0, =kernel_fault",
+        "bx r0",
+    );
+}
@@ -1,8 +1,21 @@
+// Context switch, fault and panic entry only build for the RP2040, the rest also on the host for tests
+#[cfg(target_arch = "arm")]
 pub mod context; 
 pub mod interrupts;
+#[cfg(target_arch = "arm")]
+pub mod fault;
+#[cfg(target_arch = "arm")]
+pub mod panic;
+#[cfg(target_arch = "arm")]
+pub mod sigframe;
 
+#[cfg(target_arch = "arm")]
 pub use context::*;
 pub use interrupts::*;
+#[cfg(target_arch = "arm")]
+pub use fault::*;
+#[cfg(target_arch = "arm")]
+pub(crate) use sigframe::*;
 use rp2040_hal::fugit::MicrosDurationU32;
 
 pub static QUANTUM: MicrosDurationU32 = MicrosDurationU32::micros(10_000);
@@ -0,0 +1,26 @@
+use core::panic::PanicInfo;
+use cortex_m::peripheral::{scb::VectActive, SCB};
+
+use crate::{exit_with, ExitReason, CURRENT, KERNEL_HEAP};
+
+/*
+ * Kernel panic handler. A panic in a process, a failed allocation
+ * included, only terminates that process and its supervisor decides
+ * whether it comes back. A panic in the kernel or in an interrupt halts.
+ * */
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let pid = unsafe { CURRENT };
+    defmt::error!("panic, pid {=?}: {}", pid, defmt::Display2Format(info));
+
+    if let Some(pid) = pid.filter(|_| SCB::vect_active() == VectActive::ThreadMode) {
+        // The panic may come from inside a critical section, this process never returns to it
+        unsafe { cortex_m::interrupt::enable() };
+        let reason = if KERNEL_HEAP.take_failed_for(pid) { ExitReason::OutOfMemory } else { ExitReason::Panicked };
+        exit_with(reason);
+    }
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
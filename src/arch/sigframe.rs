@@ -0,0 +1,106 @@
+use crate::{signal_trampoline, ExitReason, ProcessState, PCB};
+
+/*
+ * Signal frames, built on the process stack by the context switch.
+ *
+ *  interrupted sp -> | saved context    |
+ *                    | (padding to 8)   |
+ *  record         -> | interrupted sp   |
+ *                    | old blocked mask |
+ *                    | wake value set   |
+ *                    | wake value       |
+ *  new sp         -> | r4-r11, r0-r3, r12, lr, pc, xpsr |
+ *
+ * Switching in at the new sp enters signal_trampoline(sig, handler,
+ * record), which calls the handler and hands the record to sigreturn.
+ * A wake value left for the interrupted call is kept in the record, so
+ * blocking calls the handler makes cannot take it.
+ * */
+
+const RECORD_SIZE: usize = 4 * 4;
+const FRAME_SIZE: usize = 16 * 4;
+
+fn in_stack(pcb: &PCB, addr: usize, len: usize) -> bool {
+    let base = pcb.stack_base as usize;
+    addr >= base && addr + len <= base + pcb.stack_size
+}
+
+/*
+ * Run the handler of the lowest deliverable signal when pcb is switched
+ * in. A stack too full for the frame leaves the signal pending.
+ * */
+pub(crate) unsafe fn deliver_signal(pcb: &mut PCB) {
+    let ready = pcb.signals.deliverable();
+    if ready == 0 {
+        return;
+    }
+    let sig = ready.trailing_zeros() as u8;
+    let handler = match pcb.signals.handlers[sig as usize] {
+        Some(handler) => handler,
+        None => return,
+    };
+
+    let interrupted = pcb.sp as usize;
+    let record = (interrupted - RECORD_SIZE) & !7;
+    let sp = record - FRAME_SIZE;
+    if !in_stack(pcb, sp, interrupted - sp) {
+        return;
+    }
+
+    unsafe {
+        let record = record as *mut u32;
+        *record = interrupted as u32;
+        *record.add(1) = pcb.signals.blocked as u32;
+        let wake = pcb.wake_value.take();
+        *record.add(2) = wake.is_some() as u32;
+        *record.add(3) = wake.unwrap_or(0);
+
+        let frame = sp as *mut u32;
+        for i in 0..8 {
+            *frame.add(i) = 0;     // r4-r11
+        }
+        *frame.add(8) = sig as u32;                 // r0
+        *frame.add(9) = handler as usize as u32;    // r1
+        *frame.add(10) = record as u32;             // r2
+        *frame.add(11) = 0;                         // r3
+        *frame.add(12) = 0;                         // r12
+        *frame.add(13) = 0;                         // lr, the trampoline never returns
+        *frame.add(14) = signal_trampoline as *const () as usize as u32 | 1;
+        *frame.add(15) = 1 << 24;                   // xPSR, thumb bit
+    }
+
+    // The signal stays blocked while its handler runs
+    pcb.signals.pending &= !(1 << sig);
+    pcb.signals.blocked |= 1 << sig;
+    pcb.sp = sp as *mut u32;
+}
+
+/*
+ * Called once the context of a process that went through sigreturn is
+ * saved, puts back the context the handler interrupted. A record that
+ * does not point into the process stack faults the process.
+ * */
+pub(crate) unsafe fn restore_signal_frame(pcb: &mut PCB) {
+    let record = pcb.signals.returning as usize;
+    if record == 0 {
+        return;
+    }
+    pcb.signals.returning = core::ptr::null_mut();
+
+    if !record.is_multiple_of(4) || !in_stack(pcb, record, RECORD_SIZE) {
+        pcb.state = ProcessState::Zombie(ExitReason::Faulted);
+        return;
+    }
+    let (sp, blocked, wake) = unsafe {
+        let record = record as *const u32;
+        (*record as usize, *record.add(1) as u8, (*record.add(2) != 0).then(|| *record.add(3)))
+    };
+    if sp <= record || !in_stack(pcb, sp, FRAME_SIZE) {
+        pcb.state = ProcessState::Zombie(ExitReason::Faulted);
+        return;
+    }
+
+    pcb.sp = sp as *mut u32;
+    pcb.signals.blocked = blocked;
+    pcb.wake_value = wake;
+}
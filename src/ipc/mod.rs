@@ -0,0 +1,7 @@
+pub mod rendezvous;
+pub mod registry;
+pub mod pipe;
+
+pub use rendezvous::*;
+pub use registry::*;
+pub use pipe::*;
@@ -0,0 +1,280 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current, process_alloc, process_free, read_pipe, unblock, user_accessible, wake_pollers, write_pipe,
+    yield_now, BlockReason, Handle, HandleError, KernelObject, PollEvents, ProcessState, SchedulerError, CURRENT, PROCS};
+use core::ptr;
+
+pub const MAX_PIPES: usize = 8;
+pub const PIPE_SIZE: usize = 256;
+
+#[derive(Debug)]
+pub enum PipeError {
+    NoPipe,
+    TooManyPipes,
+    InvalidSize,
+    NoMemory,
+    BrokenPipe,     // Write with every read end closed
+    BadBuffer,      // Outside the caller's memory
+    Scheduler(SchedulerError),
+}
+
+impl From<SchedulerError> for PipeError {
+    fn from(e: SchedulerError) -> Self {
+        PipeError::Scheduler(e)
+    }
+}
+
+/*
+ * Byte stream through a ring buffer in the processes region. Each end
+ * counts the handles open on it, the pipe goes away with the last one.
+ * */
+#[derive(Clone, Copy)]
+pub struct Pipe {
+    buf: *mut u8,
+    capacity: usize,
+    head: usize,        // Next byte to read
+    len: usize,
+    readers: u8,
+    writers: u8,
+}
+
+impl Pipe {
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    // Copy out as much as buf takes, in at most two pieces around the end
+    fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.len);
+        let first = n.min(self.capacity - self.head);
+        unsafe {
+            ptr::copy_nonoverlapping(self.buf.add(self.head), buf.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(self.buf, buf.as_mut_ptr().add(first), n - first);
+        }
+        self.head = (self.head + n) % self.capacity;
+        self.len -= n;
+        n
+    }
+
+    fn push(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(self.capacity - self.len);
+        let tail = (self.head + self.len) % self.capacity;
+        let first = n.min(self.capacity - tail);
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.buf.add(tail), first);
+            ptr::copy_nonoverlapping(data.as_ptr().add(first), self.buf, n - first);
+        }
+        self.len += n;
+        n
+    }
+}
+
+pub static mut PIPES: [Option<Pipe>; MAX_PIPES] = [None; MAX_PIPES];
+
+fn get_pipe(id: u8) -> Result<&'static mut Pipe, PipeError> {
+    if id as usize >= MAX_PIPES {
+        return Err(PipeError::NoPipe);
+    }
+    unsafe { PIPES[id as usize].as_mut().ok_or(PipeError::NoPipe) }
+}
+
+/*
+ * Create a pipe of capacity bytes with no handles on it yet, see
+ * open_pipe for the usual way in
+ * */
+pub(crate) fn pipe_create(capacity: usize) -> Result<u8, PipeError> {
+    if capacity == 0 {
+        return Err(PipeError::InvalidSize);
+    }
+    cortex_m::interrupt::free(|_| unsafe {
+        let id = (0..MAX_PIPES)
+            .find(|&i| PIPES[i].is_none())
+            .ok_or(PipeError::TooManyPipes)?;
+        let buf = process_alloc(capacity).ok_or(PipeError::NoMemory)?;
+        PIPES[id] = Some(Pipe { buf, capacity, head: 0, len: 0, readers: 0, writers: 0 });
+        Ok(id as u8)
+    })
+}
+
+// Wake everything blocked on one end of the pipe, they all check again
+fn wake(id: u8, writers: bool, except: Option<u8>) {
+    wake_pollers(if writers { KernelObject::PipeWriter(id) } else { KernelObject::PipeReader(id) });
+    unsafe {
+        for pid in (0..MAX_PROCS as u8).filter(|&pid| Some(pid) != except) {
+            let waiting = matches!(PROCS[pid as usize].as_ref().map(|pcb| pcb.state),
+                Some(ProcessState::Blocked(BlockReason::WaitingForPipe { pipe, write }))
+                    if pipe == id && write == writers);
+            if waiting {
+                let _ = unblock(pid);
+            }
+        }
+    }
+}
+
+/*
+ * Read what is in the pipe, up to buf.len() bytes, waiting for a writer
+ * if it is empty. Returns 0 at end of file, once every write end is closed
+ * and the pipe drained.
+ * */
+pub(crate) fn pipe_read(id: u8, buf: &mut [u8]) -> Result<usize, PipeError> {
+    loop {
+        let read = cortex_m::interrupt::free(|_| unsafe {
+            let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+            if !user_accessible(pid, buf.as_ptr() as usize, buf.len(), true) {
+                return Err(PipeError::BadBuffer);
+            }
+            let pipe = get_pipe(id)?;
+            if !pipe.is_empty() {
+                let n = pipe.pop(buf);
+                wake(id, true, None);
+                return Ok(Some(n));
+            }
+            if pipe.writers == 0 || buf.is_empty() {
+                return Ok(Some(0));
+            }
+            block_current(BlockReason::WaitingForPipe { pipe: id, write: false })?;
+            Ok(None)
+        })?;
+
+        if let Some(n) = read {
+            return Ok(n);
+        }
+        yield_now()?;
+    }
+}
+
+/*
+ * Write as much of data as fits, waiting for a reader if the pipe is
+ * full. Returns how many bytes went in.
+ * */
+pub(crate) fn pipe_write(id: u8, data: &[u8]) -> Result<usize, PipeError> {
+    loop {
+        let written = cortex_m::interrupt::free(|_| unsafe {
+            let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+            if !user_accessible(pid, data.as_ptr() as usize, data.len(), false) {
+                return Err(PipeError::BadBuffer);
+            }
+            let pipe = get_pipe(id)?;
+            if pipe.readers == 0 {
+                return Err(PipeError::BrokenPipe);
+            }
+            if data.is_empty() {
+                return Ok(Some(0));
+            }
+            if pipe.len < pipe.capacity {
+                let n = pipe.push(data);
+                wake(id, false, None);
+                return Ok(Some(n));
+            }
+            block_current(BlockReason::WaitingForPipe { pipe: id, write: true })?;
+            Ok(None)
+        })?;
+
+        if let Some(n) = written {
+            return Ok(n);
+        }
+        yield_now()?;
+    }
+}
+
+/*
+ * What poll() sees on one end of a pipe
+ * */
+pub(crate) fn pipe_poll(object: KernelObject) -> PollEvents {
+    match object {
+        KernelObject::PipeReader(id) => match get_pipe(id) {
+            Ok(pipe) if pipe.writers == 0 && pipe.is_empty() => PollEvents::READABLE.union(PollEvents::HANGUP),
+            Ok(pipe) if !pipe.is_empty() => PollEvents::READABLE,
+            _ => PollEvents::NONE,
+        },
+        KernelObject::PipeWriter(id) => match get_pipe(id) {
+            Ok(pipe) if pipe.readers == 0 => PollEvents::HANGUP,
+            Ok(pipe) if pipe.len < pipe.capacity => PollEvents::WRITABLE,
+            _ => PollEvents::NONE,
+        },
+        _ => PollEvents::NONE,
+    }
+}
+
+/*
+ * A handle on one end of a pipe was opened
+ * */
+pub(crate) fn pipe_retain(object: KernelObject) {
+    match object {
+        KernelObject::PipeReader(id) => if let Ok(pipe) = get_pipe(id) { pipe.readers += 1 },
+        KernelObject::PipeWriter(id) => if let Ok(pipe) = get_pipe(id) { pipe.writers += 1 },
+        _ => {}
+    }
+}
+
+/*
+ * A handle holder had on one end of a pipe was closed. The last writer
+ * gives the readers end of file, the last reader fails the writers, and
+ * the pipe is freed once both ends are gone. holder is never woken, it
+ * may be a process on its way out that was blocked on this very pipe.
+ * */
+pub(crate) fn pipe_release(object: KernelObject, holder: u8) {
+    let (id, pipe) = match object {
+        KernelObject::PipeReader(id) | KernelObject::PipeWriter(id) => match get_pipe(id) {
+            Ok(pipe) => (id, pipe),
+            Err(_) => return,
+        },
+        _ => return,
+    };
+
+    if let KernelObject::PipeReader(_) = object {
+        pipe.readers = pipe.readers.saturating_sub(1);
+        if pipe.readers == 0 {
+            wake(id, true, Some(holder));
+        }
+    } else {
+        pipe.writers = pipe.writers.saturating_sub(1);
+        if pipe.writers == 0 {
+            wake(id, false, Some(holder));
+        }
+    }
+
+    if pipe.readers == 0 && pipe.writers == 0 {
+        unsafe {
+            if let Some(pipe) = PIPES[id as usize].take() {
+                process_free(pipe.buf, pipe.capacity);
+            }
+        }
+    }
+}
+
+/*
+ * embedded-io ends over pipe handles, so pipes plug into anything
+ * written against Read/Write
+ * */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PipeReader(pub Handle);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PipeWriter(pub Handle);
+
+impl embedded_io::ErrorType for PipeReader {
+    type Error = HandleError;
+}
+
+impl embedded_io::ErrorType for PipeWriter {
+    type Error = HandleError;
+}
+
+impl embedded_io::Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, HandleError> {
+        read_pipe(self.0, buf)
+    }
+}
+
+impl embedded_io::Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, HandleError> {
+        write_pipe(self.0, buf)
+    }
+
+    // Bytes are in the pipe as soon as write returns
+    fn flush(&mut self) -> Result<(), HandleError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current_until, deadline_after, deadline_passed, unblock, yield_now, BlockReason, ProcessState,
+    SchedulerError, CURRENT, PROCS};
+
+pub const MAX_SERVICES: usize = 16;
+pub const SERVICE_NAME_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum RegistryError {
+    NameTooLong,
+    AlreadyRegistered,
+    RegistryFull,
+    NotFound,
+    NotOwner,           // Only the registering process can drop a name
+    TimedOut,
+    Scheduler(SchedulerError),
+}
+
+impl From<SchedulerError> for RegistryError {
+    fn from(e: SchedulerError) -> Self {
+        RegistryError::Scheduler(e)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Service {
+    name: [u8; SERVICE_NAME_LEN],
+    name_len: u8,
+    pub pid: u8,
+}
+
+impl Service {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or("")
+    }
+}
+
+pub static mut SERVICES: [Option<Service>; MAX_SERVICES] = [None; MAX_SERVICES];
+
+/*
+ * FNV-1a of a name, what lookup waiters block on. Waiters on a name
+ * sharing the hash are woken too and just check again.
+ * */
+fn name_hash(name: &str) -> u32 {
+    name.bytes().fold(0x811C_9DC5, |h, b| (h ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+fn find(name: &str) -> Option<usize> {
+    unsafe { (0..MAX_SERVICES).find(|&i| SERVICES[i].as_ref().is_some_and(|s| s.name() == name)) }
+}
+
+/*
+ * Publish name for the calling process, until it exits or unregisters
+ * */
+pub fn register_service(name: &str) -> Result<(), RegistryError> {
+    if name.len() > SERVICE_NAME_LEN {
+        return Err(RegistryError::NameTooLong);
+    }
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+        if find(name).is_some() {
+            return Err(RegistryError::AlreadyRegistered);
+        }
+        let slot = (0..MAX_SERVICES)
+            .find(|&i| SERVICES[i].is_none())
+            .ok_or(RegistryError::RegistryFull)?;
+
+        let mut service = Service { name: [0; SERVICE_NAME_LEN], name_len: name.len() as u8, pid };
+        service.name[..name.len()].copy_from_slice(name.as_bytes());
+        SERVICES[slot] = Some(service);
+
+        let hash = name_hash(name);
+        for waiter in 0..MAX_PROCS as u8 {
+            let waiting = matches!(PROCS[waiter as usize].as_ref().map(|pcb| pcb.state),
+                Some(ProcessState::Blocked(BlockReason::WaitingForService(h))) if h == hash);
+            if waiting {
+                let _ = unblock(waiter);
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn unregister_service(name: &str) -> Result<(), RegistryError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let slot = find(name).ok_or(RegistryError::NotFound)?;
+        if SERVICES[slot].is_some_and(|s| Some(s.pid) != CURRENT) {
+            return Err(RegistryError::NotOwner);
+        }
+        SERVICES[slot] = None;
+        Ok(())
+    })
+}
+
+pub fn lookup_service(name: &str) -> Result<u8, RegistryError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let slot = find(name).ok_or(RegistryError::NotFound)?;
+        SERVICES[slot].map(|s| s.pid).ok_or(RegistryError::NotFound)
+    })
+}
+
+/*
+ * Look name up, waiting up to timeout_ms for it to be registered
+ * */
+pub fn lookup_service_wait(name: &str, timeout_ms: Option<u32>) -> Result<u8, RegistryError> {
+    if name.len() > SERVICE_NAME_LEN {
+        return Err(RegistryError::NameTooLong);
+    }
+    let deadline = deadline_after(timeout_ms);
+    let hash = name_hash(name);
+
+    loop {
+        let found = cortex_m::interrupt::free(|_| {
+            match lookup_service(name) {
+                Ok(pid) => return Ok(Some(pid)),
+                Err(RegistryError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+            if deadline_passed(deadline) {
+                return Err(RegistryError::TimedOut);
+            }
+            block_current_until(BlockReason::WaitingForService(hash), deadline)?;
+            Ok(None)
+        })?;
+
+        if let Some(pid) = found {
+            return Ok(pid);
+        }
+        yield_now()?;
+    }
+}
+
+/*
+ * Names of a process that stopped running go away with it
+ * */
+pub(crate) fn release_services(pid: u8) {
+    let services = unsafe { &mut *core::ptr::addr_of_mut!(SERVICES) };
+    for service in services.iter_mut() {
+        if service.is_some_and(|s| s.pid == pid) {
+            *service = None;
+        }
+    }
+}
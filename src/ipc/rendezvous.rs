@@ -0,0 +1,205 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current, get_pcb, switch_to, take_wake_value, unblock, unblock_with, user_accessible, wake_pollers,
+    yield_now, BlockReason, KernelObject, ProcessState, SchedulerError, CURRENT, PROCS};
+use core::ptr;
+
+/*
+ * Synchronous message passing. A client call()s a server and stays
+ * blocked until the server receive()s the message and reply()s. The
+ * kernel copies messages straight between the two processes' memory.
+ * */
+
+#[derive(Debug)]
+pub enum IpcError {
+    NoServer,
+    BadBuffer,          // Outside the caller's memory
+    NotWaiting,         // reply() to a process that is not waiting on us
+    ServerDied,         // Server stopped before replying
+    Scheduler(SchedulerError),
+}
+
+impl From<SchedulerError> for IpcError {
+    fn from(e: SchedulerError) -> Self {
+        IpcError::Scheduler(e)
+    }
+}
+
+// Handed to a client instead of a reply length when its server is gone
+const SERVER_DIED: u32 = u32::MAX;
+
+/*
+ * Client side buffers, kept in the PCB while the call is in progress
+ * */
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IpcBuffers {
+    pub send: *const u8,
+    pub send_len: usize,
+    pub reply: *mut u8,
+    pub reply_len: usize,
+}
+
+impl IpcBuffers {
+    pub const EMPTY: IpcBuffers = IpcBuffers {
+        send: ptr::null(),
+        send_len: 0,
+        reply: ptr::null_mut(),
+        reply_len: 0,
+    };
+}
+
+// Orders callers on the same server, oldest is received first
+static mut NEXT_TICKET: u32 = 0;
+
+/*
+ * Send msg to server and wait for its reply in reply_buf. Returns the
+ * length of the reply, cut to the size of reply_buf.
+ * */
+pub fn call(server: u8, msg: &[u8], reply_buf: &mut [u8]) -> Result<usize, IpcError> {
+    let wake_server = cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+        if server == pid {
+            return Err(IpcError::NoServer);
+        }
+        if !user_accessible(pid, msg.as_ptr() as usize, msg.len(), false)
+            || !user_accessible(pid, reply_buf.as_ptr() as usize, reply_buf.len(), true) {
+            return Err(IpcError::BadBuffer);
+        }
+        let srv = get_pcb(server).ok_or(IpcError::NoServer)?;
+        if matches!(srv.state, ProcessState::Zombie(_)) {
+            return Err(IpcError::NoServer);
+        }
+        let receiving = matches!(srv.state, ProcessState::Blocked(BlockReason::Receiving));
+
+        let me = get_pcb(pid).ok_or(SchedulerError::ProcessNotFound)?;
+        me.ipc = IpcBuffers {
+            send: msg.as_ptr(),
+            send_len: msg.len(),
+            reply: reply_buf.as_mut_ptr(),
+            reply_len: reply_buf.len(),
+        };
+        let ticket = NEXT_TICKET;
+        NEXT_TICKET = NEXT_TICKET.wrapping_add(1);
+        block_current(BlockReason::SendBlocked { server, ticket })?;
+
+        if receiving {
+            unblock(server)?;
+        } else {
+            wake_pollers(KernelObject::Endpoint(server));
+        }
+        Ok(receiving)
+    })?;
+
+    // The server has nothing else to do until it sees our message
+    if wake_server {
+        switch_to(server)?;
+    } else {
+        yield_now()?;
+    }
+
+    // Only reply() or the server going away unblock us, both leave a value
+    loop {
+        match cortex_m::interrupt::free(|_| take_wake_value()) {
+            Some(SERVER_DIED) => return Err(IpcError::ServerDied),
+            Some(len) => return Ok(len as usize),
+            None => yield_now()?,
+        }
+    }
+}
+
+// Oldest client blocked sending to server
+fn next_client(server: u8) -> Option<u8> {
+    unsafe {
+        (0..MAX_PROCS as u8)
+            .filter_map(|pid| match PROCS[pid as usize].as_ref().map(|pcb| pcb.state) {
+                Some(ProcessState::Blocked(BlockReason::SendBlocked { server: s, ticket })) if s == server =>
+                    Some((ticket, pid)),
+                _ => None,
+            })
+            .min()
+            .map(|(_, pid)| pid)
+    }
+}
+
+/*
+ * Some call is waiting for server to receive it
+ * */
+pub(crate) fn ipc_pending(server: u8) -> bool {
+    next_client(server).is_some()
+}
+
+/*
+ * Wait for a call and copy its message into buf. Returns the client to
+ * reply to and the message length, cut to the size of buf.
+ * */
+pub fn receive(buf: &mut [u8]) -> Result<(u8, usize), IpcError> {
+    loop {
+        let received = cortex_m::interrupt::free(|_| unsafe {
+            let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+            if !user_accessible(pid, buf.as_ptr() as usize, buf.len(), true) {
+                return Err(IpcError::BadBuffer);
+            }
+
+            let client = match next_client(pid) {
+                Some(client) => client,
+                None => {
+                    block_current(BlockReason::Receiving)?;
+                    return Ok(None);
+                }
+            };
+
+            let c = get_pcb(client).ok_or(SchedulerError::ProcessNotFound)?;
+            let len = c.ipc.send_len.min(buf.len());
+            ptr::copy_nonoverlapping(c.ipc.send, buf.as_mut_ptr(), len);
+            c.state = ProcessState::Blocked(BlockReason::ReplyBlocked(pid));
+            Ok(Some((client, len)))
+        })?;
+
+        if let Some(received) = received {
+            return Ok(received);
+        }
+        yield_now()?;
+    }
+}
+
+/*
+ * Copy msg into the reply buffer of client and let it run again
+ * */
+pub fn reply(client: u8, msg: &[u8]) -> Result<(), IpcError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+        if !user_accessible(pid, msg.as_ptr() as usize, msg.len(), false) {
+            return Err(IpcError::BadBuffer);
+        }
+        let c = get_pcb(client).ok_or(IpcError::NotWaiting)?;
+        if !matches!(c.state, ProcessState::Blocked(BlockReason::ReplyBlocked(server)) if server == pid) {
+            return Err(IpcError::NotWaiting);
+        }
+
+        let len = msg.len().min(c.ipc.reply_len);
+        ptr::copy_nonoverlapping(msg.as_ptr(), c.ipc.reply, len);
+        c.ipc = IpcBuffers::EMPTY;
+        unblock_with(client, len as u32)?;
+        Ok(())
+    })
+}
+
+/*
+ * A server that stops running fails every call queued on it or waiting
+ * for its reply
+ * */
+pub(crate) fn release_ipc(pid: u8) {
+    unsafe {
+        for client in 0..MAX_PROCS as u8 {
+            let waiting = matches!(PROCS[client as usize].as_ref().map(|pcb| pcb.state),
+                Some(ProcessState::Blocked(BlockReason::SendBlocked { server, .. }
+                    | BlockReason::ReplyBlocked(server))) if server == pid);
+            if waiting {
+                let _ = unblock_with(client, SERVER_DIED);
+            }
+        }
+        if let Some(pcb) = PROCS[pid as usize].as_mut() {
+            pcb.ipc = IpcBuffers::EMPTY;
+        }
+    }
+}
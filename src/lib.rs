@@ -1,12 +1,24 @@
 #![no_std]
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
 pub mod process;
 pub mod arch; 
 pub mod memory;
 pub mod scheduler;
 pub mod syscall; 
+pub mod sync;
+pub mod ipc;
+pub mod timer;
+#[cfg(test)]
+mod test_support;
 
 pub use process::*;
 pub use arch::*;
 pub use memory::*;
 pub use scheduler::*;
 pub use syscall::*;
+pub use sync::*;
+pub use ipc::*;
+pub use timer::*;
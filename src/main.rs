@@ -1,17 +1,14 @@
 #![no_std]
 #![no_main]
 
-use panic_halt as _;
+use defmt_rtt as _;
 use rp2040_hal::{self as hal, fugit::ExtU32, timer::Alarm};
 // Some traits we need
 use embedded_hal::delay::DelayNs;
 use embedded_hal::digital::OutputPin;
 use hal::pac;
-use rp2040_hal::gpio::{Pin, FunctionSioOutput, PullDown};
-use hal::gpio::bank0::{Gpio0, Gpio1};
-use core::ptr;
 
-use jpkernel::{create_process, set_alarm, sleep_ms, start_first_process, MemoryLayout, Scheduler, CURRENT, PROCS, QUANTUM, SCHEDULER};
+use jpkernel::{init_kernel_heap, load_apps, reserve_gpio, set_alarm, sleep_ms, start_first_process, start_timer_service, MemoryLayout, ProcessBuilder, Scheduler, CURRENT, PROCS, QUANTUM, SCHEDULER};
 
 #[unsafe(link_section = ".boot2")]
 #[used]
@@ -23,12 +20,6 @@ pub static BOOT2: [u8; 256] = rp2040_boot2::BOOT_LOADER_GENERIC_03H;
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
 
-type Led0 = Pin<Gpio0, FunctionSioOutput, PullDown>;
-type Led1 = Pin<Gpio1, FunctionSioOutput, PullDown>;
-pub static mut LED0: Option<Led0> = None;
-pub static mut LED1: Option<Led1> = None;
-pub static mut TIMER: Option<hal::Timer> = None;
-
 #[rp2040_hal::entry]
 fn main() -> ! {
     // Grab our singleton objects
@@ -71,32 +62,53 @@ fn main() -> ! {
     // Configure GPIO0 as an output
     let mut led_pin0 = pins.gpio0.into_push_pull_output(); 
     let mut led_pin1 = pins.gpio1.into_push_pull_output(); 
+    // LEDs belong to the kernel blinkers, no process may reconfigure them
+    reserve_gpio(0b11);
     let stack_size = 1024; 
 
     let lay = MemoryLayout::new();
+    // Nothing below is safe to run on a broken linker script
+    lay.validate().expect("invalid memory layout");
+
     unsafe {
         // Set up MSP kernel region 
         core::arch::asm!(
             "msr msp, {0}",
             in(reg) (lay.kernel_data.start + lay.kernel_data.size) as u32,
         );
+    }
+
+    // Kernel heap lives below the MSP stack, alloc is usable from here on
+    init_kernel_heap();
 
+    unsafe {
         // Unmask interrupt 
         pac::NVIC::unmask(pac::Interrupt::TIMER_IRQ_0);
-        LED0 = Some(led_pin0);
-        LED1 = Some(led_pin1);
-        TIMER = Some(timer);
+        pac::NVIC::unmask(pac::Interrupt::TIMER_IRQ_1);
 
         jpkernel::register_timer(&timer);
-
-        create_process(stack_size, blink_fast, core::ptr::null_mut())
-            .unwrap();
-        create_process(stack_size, blink_slow, core::ptr::null_mut())
-            .unwrap();
-
-        // Should not return 
-        start_first_process();
     }
+
+    // Each blinker owns its pin, no shared statics or unsafe needed
+    ProcessBuilder::default()
+        .name("blink_fast")
+        .stack_size(stack_size)
+        .spawn_closure(move || blink(&mut led_pin0, timer, 20))
+        .unwrap();
+    ProcessBuilder::default()
+        .name("blink_slow")
+        .stack_size(stack_size)
+        .spawn_closure(move || blink(&mut led_pin1, timer, 20))
+        .unwrap();
+
+    // Software timers fire from Alarm1, Alarm0 drives the quantum
+    start_timer_service(timer.alarm_1().unwrap()).unwrap();
+
+    // Applications flashed separately from the kernel
+    load_apps();
+
+    // Should not return 
+    start_first_process();
     
     #[allow(unreachable_code)]
     loop {
@@ -107,58 +119,14 @@ fn main() -> ! {
         timer.delay_ms(500);*/
     }
 }
-fn blink_fast(_arg: *mut ()) -> ! {    
-    loop {
-        unsafe {
-            let led = ptr::addr_of_mut!(LED0)
-                .cast::<Option<Led0>>()
-                .as_mut()
-                .unwrap()
-                .as_mut()
-                .unwrap();
-            
-            let timer = ptr::addr_of_mut!(TIMER)
-                .cast::<Option<hal::Timer>>()
-                .as_mut()
-                .unwrap()
-                .as_mut()
-                .unwrap();
-            
-            led.set_high().unwrap();
-            //sleep_ms(20).ok();
-            timer.delay_ms(20);
-            led.set_low().unwrap();
-            timer.delay_ms(20);
-            //sleep_ms(20).ok();
-        }
-    }
-}
 
-fn blink_slow(_arg: *mut ()) -> ! {
+fn blink(led: &mut impl OutputPin, mut timer: hal::Timer, period_ms: u32) {
     loop {
-        unsafe {
-            let led = ptr::addr_of_mut!(LED1)
-                .cast::<Option<Led1>>()
-                .as_mut()
-                .unwrap()
-                .as_mut()
-                .unwrap();
-            
-            let timer = ptr::addr_of_mut!(TIMER)
-                .cast::<Option<hal::Timer>>()
-                .as_mut()
-                .unwrap()
-                .as_mut()
-                .unwrap();
-            
-            led.set_high().unwrap();
-            //sleep_ms(20).ok();
-            timer.delay_ms(20);
-            led.set_low().unwrap();
-            timer.delay_ms(20);
-            //sleep_ms(20).ok();
-        }
+        led.set_high().unwrap();
+        //sleep_ms(period_ms).ok();
+        timer.delay_ms(period_ms);
+        led.set_low().unwrap();
+        timer.delay_ms(period_ms);
+        //sleep_ms(period_ms).ok();
     }
 }
-
-
@@ -0,0 +1,39 @@
+use crate::{get_pcb, shm_covers, MemoryLayout, MemoryRegion};
+
+/*
+ * Whether process pid may touch [addr, addr + len). A process owns its
+ * heap and stack block, the RAM of its loaded image and the shared
+ * memory it was granted. Built in processes are part of the kernel
+ * image, so its statics count as theirs too.
+ * */
+pub fn user_accessible(pid: u8, addr: usize, len: usize, write: bool) -> bool {
+    let pcb = match get_pcb(pid) {
+        Some(pcb) => pcb,
+        None => return false,
+    };
+    if addr.checked_add(len).is_none() {
+        return false;
+    }
+    let range = MemoryRegion { start: addr, size: len };
+
+    let block = MemoryRegion {
+        start: pcb.heap_base as usize,
+        size: pcb.heap_limit + pcb.stack_size,
+    };
+    if block.encloses(&range) {
+        return true;
+    }
+
+    if pcb.image_base.is_null() {
+        if MemoryLayout::new().kernel_data.encloses(&range) {
+            return true;
+        }
+    } else {
+        let image = MemoryRegion { start: pcb.image_base as usize, size: pcb.image_size };
+        if image.encloses(&range) {
+            return true;
+        }
+    }
+
+    shm_covers(pid, addr, len, write)
+}
@@ -0,0 +1,151 @@
+use crate::layout::{MemoryLayout, MemoryRegion};
+
+const MAX_FREE_BLOCKS: usize = 32;
+
+// Every allocation is 8 byte aligned, what AAPCS wants for stacks
+pub const ALLOC_ALIGN: usize = 8;
+
+const NO_BLOCK: MemoryRegion = MemoryRegion { start: 0, size: 0 };
+
+/*
+ * First fit allocator over a memory region.
+ * Free blocks are kept sorted by address so neighbours can be merged back.
+ * */
+pub struct RegionAllocator {
+    free: [MemoryRegion; MAX_FREE_BLOCKS],
+    count: usize,
+    initialized: bool,
+}
+
+impl RegionAllocator {
+    pub const fn new() -> Self {
+        Self {
+            free: [NO_BLOCK; MAX_FREE_BLOCKS],
+            count: 0,
+            initialized: false,
+        }
+    }
+
+    pub fn init(&mut self, region: MemoryRegion) {
+        let start = (region.start + ALLOC_ALIGN - 1) & !(ALLOC_ALIGN - 1);
+        self.free[0] = MemoryRegion { start, size: region.end() - start };
+        self.count = 1;
+        self.initialized = true;
+    }
+
+    pub fn is_initialized(&self) -> bool { self.initialized }
+
+    pub fn free_bytes(&self) -> usize {
+        self.free[..self.count].iter().map(|b| b.size).sum()
+    }
+
+    // Biggest request that can still succeed
+    pub fn largest_free(&self) -> usize {
+        self.free[..self.count].iter().map(|b| b.size).max().unwrap_or(0)
+    }
+
+    pub fn free_block_count(&self) -> usize { self.count }
+
+    pub fn alloc(&mut self, size: usize) -> Option<*mut u8> {
+        if size == 0 {
+            return None;
+        }
+        let size = (size + ALLOC_ALIGN - 1) & !(ALLOC_ALIGN - 1);
+
+        for i in 0..self.count {
+            if self.free[i].size < size {
+                continue;
+            }
+
+            let addr = self.free[i].start;
+            self.free[i].start += size;
+            self.free[i].size -= size;
+
+            if self.free[i].size == 0 {
+                self.remove(i);
+            }
+            return Some(addr as *mut u8);
+        }
+
+        None
+    }
+
+    /*
+     * Give a block back, merging it with its neighbours.
+     * Returns false if the block had to be leaked because the table is full
+     * */
+    pub fn free(&mut self, ptr: *mut u8, size: usize) -> bool {
+        if size == 0 {
+            return true;
+        }
+        let start = ptr as usize;
+        let size = (size + ALLOC_ALIGN - 1) & !(ALLOC_ALIGN - 1);
+
+        // First free block past the one we give back
+        let mut idx = 0;
+        while idx < self.count && self.free[idx].start < start {
+            idx += 1;
+        }
+
+        let merge_prev = idx > 0 && self.free[idx - 1].end() == start;
+        let merge_next = idx < self.count && start + size == self.free[idx].start;
+
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                self.free[idx - 1].size += size + self.free[idx].size;
+                self.remove(idx);
+            }
+            (true, false) => self.free[idx - 1].size += size,
+            (false, true) => {
+                self.free[idx].start = start;
+                self.free[idx].size += size;
+            }
+            (false, false) => {
+                if self.count == MAX_FREE_BLOCKS {
+                    return false;
+                }
+                for i in (idx..self.count).rev() {
+                    self.free[i + 1] = self.free[i];
+                }
+                self.free[idx] = MemoryRegion { start, size };
+                self.count += 1;
+            }
+        }
+        true
+    }
+
+    fn remove(&mut self, idx: usize) {
+        for i in idx..self.count - 1 {
+            self.free[i] = self.free[i + 1];
+        }
+        self.count -= 1;
+    }
+}
+
+impl Default for RegionAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+ * Allocator for the processes region, stacks come from here
+ * */
+pub static mut PROCESS_MEMORY: RegionAllocator = RegionAllocator::new();
+
+pub fn process_alloc(size: usize) -> Option<*mut u8> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let mem = core::ptr::addr_of_mut!(PROCESS_MEMORY);
+        if !(*mem).is_initialized() {
+            (*mem).init(MemoryLayout::new().processes);
+        }
+        (*mem).alloc(size)
+    })
+}
+
+pub fn process_free(ptr: *mut u8, size: usize) {
+    cortex_m::interrupt::free(|_| unsafe {
+        let mem = core::ptr::addr_of_mut!(PROCESS_MEMORY);
+        (*mem).free(ptr, size);
+    })
+}
@@ -0,0 +1,131 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use embedded_alloc::Heap;
+
+use crate::{MemoryLayout, CURRENT};
+
+// failed_pid when the last failure was not from a process
+const NO_PID: u8 = u8::MAX;
+
+/*
+ * Global allocator over MemoryLayout::kernel_heap.
+ * A failed allocation is logged and returns null like any allocator.
+ * try_reserve and friends see the error and carry on, anything else ends
+ * in handle_alloc_error, whose panic terminates only the process that
+ * asked (see arch/panic.rs). In the kernel that panic halts.
+ * */
+pub struct KernelHeap {
+    heap: Heap,
+    failures: AtomicUsize,
+    failed_pid: AtomicU8,   // Process behind the last failure
+}
+
+impl KernelHeap {
+    pub const fn new() -> Self {
+        Self {
+            heap: Heap::empty(),
+            failures: AtomicUsize::new(0),
+            failed_pid: AtomicU8::new(NO_PID),
+        }
+    }
+
+    pub fn used(&self) -> usize { self.heap.used() }
+
+    pub fn free(&self) -> usize { self.heap.free() }
+
+    pub fn failures(&self) -> usize { self.failures.load(Ordering::Relaxed) }
+
+    /*
+     * Whether the last failed allocation came from pid, clearing it so a
+     * later panic of the same or a reused pid is not taken for one
+     * */
+    pub fn take_failed_for(&self, pid: u8) -> bool {
+        cortex_m::interrupt::free(|_| {
+            let failed = self.failed_pid.load(Ordering::Relaxed) == pid;
+            if failed {
+                self.failed_pid.store(NO_PID, Ordering::Relaxed);
+            }
+            failed
+        })
+    }
+
+    // Only counts and logs, the caller decides what a null pointer means
+    fn alloc_failed(&self, layout: Layout) {
+        let pid = unsafe { CURRENT };
+        // No atomic read-modify-write on the M0+
+        cortex_m::interrupt::free(|_| {
+            let count = self.failures.load(Ordering::Relaxed);
+            self.failures.store(count + 1, Ordering::Relaxed);
+            self.failed_pid.store(pid.unwrap_or(NO_PID), Ordering::Relaxed);
+        });
+
+        defmt::error!("kernel heap: {=usize} bytes (align {=usize}) failed, pid {=?}, {=usize} free",
+            layout.size(), layout.align(), pid, self.free());
+    }
+}
+
+impl Default for KernelHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.heap.alloc(layout) };
+        if ptr.is_null() {
+            self.alloc_failed(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.heap.dealloc(ptr, layout) }
+    }
+}
+
+#[cfg_attr(target_arch = "arm", global_allocator)]
+pub static KERNEL_HEAP: KernelHeap = KernelHeap::new();
+
+/*
+ * Must run once at boot, before anything touches alloc
+ * */
+pub fn init_kernel_heap() {
+    let region = MemoryLayout::new().kernel_heap;
+    unsafe { KERNEL_HEAP.heap.init(region.start, region.size) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::kernel;
+    use crate::{new_process, reap, ProcessBuilder};
+
+    // The allocator and its log line need the RP2040 spinlocks, record the failure by hand
+    fn fail_as(heap: &KernelHeap, pid: u8) {
+        heap.failed_pid.store(pid, Ordering::Relaxed);
+    }
+
+    fn body(_arg: *mut ()) -> ! {
+        loop {}
+    }
+
+    #[test]
+    fn failure_is_taken_once() {
+        let heap = KernelHeap::new();
+        fail_as(&heap, 3);
+        assert!(!heap.take_failed_for(4));
+        assert!(heap.take_failed_for(3));
+        assert!(!heap.take_failed_for(3));
+    }
+
+    #[test]
+    fn reap_forgets_failure() {
+        let _kernel = kernel();
+        let pid = new_process(&ProcessBuilder::new(body)).unwrap();
+        fail_as(&KERNEL_HEAP, pid);
+
+        reap(pid);
+        assert!(!KERNEL_HEAP.take_failed_for(pid));
+    }
+}
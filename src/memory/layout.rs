@@ -1,4 +1,4 @@
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub struct MemoryRegion {
     pub start: usize, 
     pub size: usize, 
@@ -7,21 +7,135 @@ pub struct MemoryRegion {
 impl MemoryRegion {
     // Return the end address of the memory region
     pub fn end(&self) -> usize {
-        self.start + self.size 
+        self.start.saturating_add(self.size)
     }
 
     // Within memory region 
     pub fn contains(&self, addr: usize) -> bool {
         addr >= self.start && addr < self.end()
     }
+
+    // Share at least one byte
+    pub fn overlaps(&self, other: &MemoryRegion) -> bool {
+        self.start < other.end() && other.start < self.end()
+    }
+
+    // Other lies entirely inside this region
+    pub fn encloses(&self, other: &MemoryRegion) -> bool {
+        other.start >= self.start && other.end() <= self.end()
+    }
+
+    pub fn is_aligned(&self, align: usize) -> bool {
+        self.start.is_multiple_of(align) && self.size.is_multiple_of(align)
+    }
+
+    /*
+     * Cut the region in two at offset, None if offset is past the end
+     * */
+    pub fn split(&self, offset: usize) -> Option<(MemoryRegion, MemoryRegion)> {
+        if offset > self.size {
+            return None;
+        }
+        let low = MemoryRegion { start: self.start, size: offset };
+        let high = MemoryRegion { start: self.start.saturating_add(offset), size: self.size - offset };
+        Some((low, high))
+    }
+
+    /*
+     * Largest part of the region starting on an align boundary,
+     * align must be a power of two. Empty at the end if no boundary is left.
+     * */
+    pub fn align_up(&self, align: usize) -> MemoryRegion {
+        match checked_align_up(self.start, align) {
+            Some(start) => MemoryRegion { start, size: self.end().saturating_sub(start) },
+            None => MemoryRegion { start: self.end(), size: 0 },
+        }
+    }
+}
+
+// Round addr up to a power of two boundary
+pub const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+// Same, None when the boundary is past the top of the address space
+pub const fn checked_align_up(addr: usize, align: usize) -> Option<usize> {
+    match addr.checked_add(align - 1) {
+        Some(addr) => Some(addr & !(align - 1)),
+        None => None,
+    }
+}
+
+// RP2040 SRAM, the striped banks 0-3 followed by banks 4 and 5
+pub const SRAM_START: usize = 0x2000_0000;
+pub const SRAM_SIZE: usize = 264 * 1024;
+
+// Everything in SRAM is handed out as 8 byte aligned stacks and blocks
+pub const REGION_ALIGN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionName {
+    KernelData,
+    KernelHeap,
+    Wifi,
+    Processes,
+    Core0Stack,
+    Core1Stack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayoutConflict {
+    Empty(RegionName),
+    OutsideSram(RegionName),
+    Misaligned(RegionName),
+    Overlap(RegionName, RegionName),
+    NotInside(RegionName, RegionName),  // First region must sit within the second
+}
+
+// Worst case is every pair of the SRAM regions overlapping
+const MAX_CONFLICTS: usize = 16;
+
+/*
+ * Every conflict found by MemoryLayout::validate, not only the first
+ * */
+#[derive(Debug)]
+pub struct LayoutError {
+    conflicts: [Option<LayoutConflict>; MAX_CONFLICTS],
+    count: usize,
+}
+
+impl LayoutError {
+    const fn new() -> Self {
+        Self { conflicts: [None; MAX_CONFLICTS], count: 0 }
+    }
+
+    // Extra conflicts past MAX_CONFLICTS are dropped, the list is never empty anyway
+    fn push(&mut self, conflict: LayoutConflict) {
+        if self.count < MAX_CONFLICTS {
+            self.conflicts[self.count] = Some(conflict);
+            self.count += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize { self.count }
+
+    pub fn is_empty(&self) -> bool { self.count == 0 }
+
+    pub fn conflicts(&self) -> impl Iterator<Item = LayoutConflict> + '_ {
+        self.conflicts[..self.count].iter().flatten().copied()
+    }
 }
 
 
 #[derive(Clone, Copy)]
 pub struct MemoryLayout {
     pub kernel_data: MemoryRegion, 
+    pub kernel_heap: MemoryRegion,  // Inside kernel_data, after the statics
     pub wifi: MemoryRegion, 
     pub processes: MemoryRegion, 
+    pub apps: MemoryRegion,         // Flash partition with application images
+    pub core0_stack: MemoryRegion,
+    pub core1_stack: MemoryRegion,
 }
 
 impl MemoryLayout {
@@ -29,13 +143,21 @@ impl MemoryLayout {
         unsafe extern "C" {
             // These are addresses
             static _kernel_data_start: u8;
+            static _kernel_heap_start: u8;
             static _wifi_start: u8; 
             static _processes_start: u8;
+            static _apps_start: u8;
+            static _core0_stack_start: u8;
+            static _core1_stack_start: u8;
             
             // These are VALUES, not addresses - don't dereference!
             static _kernel_data_size: usize;
+            static _kernel_heap_size: usize;
             static _wifi_size: usize;
             static _processes_size: usize; 
+            static _apps_size: usize;
+            static _core0_stack_size: usize;
+            static _core1_stack_size: usize;
         }
 
         unsafe {
@@ -44,6 +166,11 @@ impl MemoryLayout {
                 size: &_kernel_data_size as *const usize as usize,  // Address IS the value
             }; 
 
+            let kernel_heap = MemoryRegion {
+                start: &_kernel_heap_start as *const u8 as usize,
+                size: &_kernel_heap_size as *const usize as usize,
+            };
+
             let wifi = MemoryRegion{
                 start: &_wifi_start as *const u8 as usize,  
                 size: &_wifi_size as *const usize as usize,
@@ -54,7 +181,191 @@ impl MemoryLayout {
                 size: &_processes_size as *const usize as usize,
             }; 
 
-            Self { kernel_data, wifi, processes }
+            let apps = MemoryRegion {
+                start: &_apps_start as *const u8 as usize,
+                size: &_apps_size as *const usize as usize,
+            };
+
+            let core0_stack = MemoryRegion {
+                start: &_core0_stack_start as *const u8 as usize,
+                size: &_core0_stack_size as *const usize as usize,
+            };
+
+            let core1_stack = MemoryRegion {
+                start: &_core1_stack_start as *const u8 as usize,
+                size: &_core1_stack_size as *const usize as usize,
+            };
+
+            Self { kernel_data, kernel_heap, wifi, processes, apps, core0_stack, core1_stack }
         }
     }
+
+    /*
+     * Check the SRAM regions are non empty, aligned, inside SRAM and
+     * disjoint. The kernel heap is the exception, it lives in kernel_data.
+     * Flash (apps) is not checked here.
+     * */
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        let sram = MemoryRegion { start: SRAM_START, size: SRAM_SIZE };
+        let regions = [
+            (RegionName::KernelData, self.kernel_data),
+            (RegionName::Wifi, self.wifi),
+            (RegionName::Processes, self.processes),
+            (RegionName::Core0Stack, self.core0_stack),
+            (RegionName::Core1Stack, self.core1_stack),
+        ];
+
+        let mut err = LayoutError::new();
+        for (i, &(name, region)) in regions.iter().enumerate() {
+            if region.size == 0 {
+                err.push(LayoutConflict::Empty(name));
+                continue;
+            }
+            if !sram.encloses(&region) {
+                err.push(LayoutConflict::OutsideSram(name));
+            }
+            if !region.is_aligned(REGION_ALIGN) {
+                err.push(LayoutConflict::Misaligned(name));
+            }
+            for &(other_name, other) in &regions[i + 1..] {
+                if region.overlaps(&other) {
+                    err.push(LayoutConflict::Overlap(name, other_name));
+                }
+            }
+        }
+
+        if self.kernel_heap.size == 0 {
+            err.push(LayoutConflict::Empty(RegionName::KernelHeap));
+        } else {
+            if !self.kernel_data.encloses(&self.kernel_heap) {
+                err.push(LayoutConflict::NotInside(RegionName::KernelHeap, RegionName::KernelData));
+            }
+            if !self.kernel_heap.is_aligned(REGION_ALIGN) {
+                err.push(LayoutConflict::Misaligned(RegionName::KernelHeap));
+            }
+        }
+
+        if err.is_empty() { Ok(()) } else { Err(err) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    const fn region(start: usize, size: usize) -> MemoryRegion {
+        MemoryRegion { start, size }
+    }
+
+    // Fits the 264K of SRAM with a gap before the core stacks
+    fn layout() -> MemoryLayout {
+        MemoryLayout {
+            kernel_data: region(0x2000_0000, 0x1_0000),
+            kernel_heap: region(0x2000_8000, 0x8000),
+            wifi: region(0x2001_0000, 0x8000),
+            processes: region(0x2001_8000, 0x2_0000),
+            apps: region(0x1008_0000, 0x8_0000),
+            core0_stack: region(0x2004_0000, 0x1000),
+            core1_stack: region(0x2004_1000, 0x1000),
+        }
+    }
+
+    fn conflicts(layout: &MemoryLayout) -> Vec<LayoutConflict> {
+        layout.validate().unwrap_err().conflicts().collect()
+    }
+
+    #[test]
+    fn accepts_valid_layout() {
+        assert!(layout().validate().is_ok());
+    }
+
+    #[test]
+    fn reports_overlap() {
+        let mut lay = layout();
+        lay.wifi = region(0x2000_F000, 0x8000);
+        assert_eq!(conflicts(&lay), [LayoutConflict::Overlap(RegionName::KernelData, RegionName::Wifi)]);
+    }
+
+    #[test]
+    fn reports_region_past_sram() {
+        let mut lay = layout();
+        // Ends one page past the 264K
+        lay.core1_stack = region(0x2004_1000, 0x2000);
+        assert_eq!(conflicts(&lay), [LayoutConflict::OutsideSram(RegionName::Core1Stack)]);
+
+        lay.core1_stack = region(0x1000_0000, 0x1000);
+        assert_eq!(conflicts(&lay), [LayoutConflict::OutsideSram(RegionName::Core1Stack)]);
+    }
+
+    #[test]
+    fn reports_misalignment() {
+        let mut lay = layout();
+        lay.core0_stack = region(0x2004_0004, 0xFF8);
+        lay.kernel_heap = region(0x2000_8000, 0x7FFC);
+        assert_eq!(conflicts(&lay), [
+            LayoutConflict::Misaligned(RegionName::Core0Stack),
+            LayoutConflict::Misaligned(RegionName::KernelHeap),
+        ]);
+    }
+
+    #[test]
+    fn reports_every_conflict() {
+        let mut lay = layout();
+        lay.wifi = region(0x2000_F000, 0x8000);
+        lay.processes = region(0x2001_8000, 0x3_0000);
+        lay.core1_stack = region(0, 0);
+        lay.kernel_heap = region(0x2001_0000, 0x1000);
+        assert_eq!(conflicts(&lay), [
+            LayoutConflict::Overlap(RegionName::KernelData, RegionName::Wifi),
+            LayoutConflict::OutsideSram(RegionName::Processes),
+            LayoutConflict::Overlap(RegionName::Processes, RegionName::Core0Stack),
+            LayoutConflict::Empty(RegionName::Core1Stack),
+            LayoutConflict::NotInside(RegionName::KernelHeap, RegionName::KernelData),
+        ]);
+    }
+
+    #[test]
+    fn split_edges() {
+        let r = region(0x2000_0000, 0x100);
+        let (low, high) = r.split(0).unwrap();
+        assert_eq!((low.start, low.size, high.start, high.size), (0x2000_0000, 0, 0x2000_0000, 0x100));
+
+        let (low, high) = r.split(0x100).unwrap();
+        assert_eq!((low.size, high.start, high.size), (0x100, 0x2000_0100, 0));
+
+        let (low, high) = r.split(0x40).unwrap();
+        assert_eq!((low.end(), high.start, high.end()), (0x2000_0040, 0x2000_0040, r.end()));
+
+        assert!(r.split(0x101).is_none());
+    }
+
+    #[test]
+    fn align_up_edges() {
+        // Already aligned is left alone
+        let r = region(0x2000_0000, 0x100).align_up(8);
+        assert_eq!((r.start, r.size), (0x2000_0000, 0x100));
+
+        let r = region(0x2000_0001, 0x100).align_up(8);
+        assert_eq!((r.start, r.size), (0x2000_0008, 0xF9));
+
+        // Zero size, or too small to reach a boundary, ends up empty
+        let r = region(0x2000_0001, 0).align_up(8);
+        assert_eq!(r.size, 0);
+        let r = region(0x2000_0001, 4).align_up(8);
+        assert_eq!(r.size, 0);
+
+        // No boundary left below the top of the address space
+        let r = region(usize::MAX - 2, 2).align_up(8);
+        assert_eq!((r.start, r.size), (usize::MAX, 0));
+        assert_eq!(checked_align_up(usize::MAX - 2, 8), None);
+        assert_eq!(checked_align_up(0x1001, 0x1000), Some(0x2000));
+    }
+
+    #[test]
+    fn end_saturates() {
+        let r = region(usize::MAX - 1, 0x10);
+        assert_eq!(r.end(), usize::MAX);
+        assert!(r.contains(usize::MAX - 1));
+    }
 }
@@ -1,3 +1,15 @@
 pub mod layout; 
+pub mod allocator;
+pub mod heap;
+pub mod pool;
+pub mod report;
+pub mod shm;
+pub mod access;
 
 pub use layout::*;
+pub use allocator::*;
+pub use heap::*;
+pub use pool::*;
+pub use report::*;
+pub use shm::*;
+pub use access::*;
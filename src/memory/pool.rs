@@ -0,0 +1,265 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current, process_alloc, process_free, unblock, wake_pollers, yield_now, KernelObject, BlockReason, ProcessState, SchedulerError, CURRENT, PROCS};
+use core::ptr;
+
+pub const MAX_POOLS: usize = 8;
+pub const MAX_POOL_BLOCKS: usize = 64;
+
+// Owner of a free block, and of blocks taken by kernel code
+const NO_OWNER: u8 = 0xFF;
+const KERNEL_OWNER: u8 = 0xFE;
+
+#[derive(Debug)]
+pub enum PoolError {
+    NoPool,
+    TooManyPools,
+    InvalidSize,
+    NoMemory,
+    Exhausted,          // Every block is in use
+    InvalidBlock,       // Not a block of this pool, or already free
+    NotOwner,           // Block taken by another process
+    Scheduler(SchedulerError),
+}
+
+impl From<SchedulerError> for PoolError {
+    fn from(e: SchedulerError) -> Self {
+        PoolError::Scheduler(e)
+    }
+}
+
+/*
+ * N blocks of S bytes. Free blocks are chained through their first word,
+ * so alloc and free are O(1) and never touch a general purpose allocator.
+ * */
+#[derive(Clone, Copy)]
+pub struct MemoryPool {
+    base: *mut u8,
+    block_size: usize,
+    count: usize,
+    free_head: *mut u8,
+    free_count: usize,
+    owners: [u8; MAX_POOL_BLOCKS],
+    handles: u8,
+    closed: bool,       // Last handle gone, storage goes once every block is back
+}
+
+impl MemoryPool {
+    /*
+     * Lay out a pool over count blocks of block_size at base
+     * */
+    pub fn new(base: *mut u8, block_size: usize, count: usize) -> Self {
+        let mut pool = Self {
+            base,
+            block_size,
+            count,
+            free_head: ptr::null_mut(),
+            free_count: 0,
+            owners: [NO_OWNER; MAX_POOL_BLOCKS],
+            handles: 0,
+            closed: false,
+        };
+        for idx in (0..count).rev() {
+            pool.push_free(idx);
+        }
+        pool
+    }
+
+    fn block(&self, idx: usize) -> *mut u8 {
+        unsafe { self.base.add(idx * self.block_size) }
+    }
+
+    fn index_of(&self, block: *mut u8) -> Option<usize> {
+        let offset = (block as usize).checked_sub(self.base as usize)?;
+        let idx = offset / self.block_size;
+        if offset % self.block_size != 0 || idx >= self.count {
+            return None;
+        }
+        Some(idx)
+    }
+
+    fn push_free(&mut self, idx: usize) {
+        let block = self.block(idx);
+        unsafe { (block as *mut *mut u8).write(self.free_head) };
+        self.free_head = block;
+        self.free_count += 1;
+        self.owners[idx] = NO_OWNER;
+    }
+
+    pub fn alloc(&mut self, owner: u8) -> Option<*mut u8> {
+        if self.free_head.is_null() {
+            return None;
+        }
+        let block = self.free_head;
+        self.free_head = unsafe { (block as *mut *mut u8).read() };
+        self.free_count -= 1;
+
+        let idx = self.index_of(block)?;
+        self.owners[idx] = owner;
+        Some(block)
+    }
+
+    /*
+     * Return a block taken by owner, the kernel may return any block
+     * */
+    pub fn free(&mut self, block: *mut u8, owner: u8) -> Result<(), PoolError> {
+        let idx = self.index_of(block).ok_or(PoolError::InvalidBlock)?;
+        if self.owners[idx] == NO_OWNER {
+            return Err(PoolError::InvalidBlock);
+        }
+        if owner != KERNEL_OWNER && self.owners[idx] != owner {
+            return Err(PoolError::NotOwner);
+        }
+        self.push_free(idx);
+        Ok(())
+    }
+
+    /*
+     * Return every block held by owner, count of blocks released
+     * */
+    pub fn release_owner(&mut self, owner: u8) -> usize {
+        let mut released = 0;
+        for idx in 0..self.count {
+            if self.owners[idx] == owner {
+                self.push_free(idx);
+                released += 1;
+            }
+        }
+        released
+    }
+
+    pub fn block_size(&self) -> usize { self.block_size }
+
+    pub fn free_blocks(&self) -> usize { self.free_count }
+}
+
+pub static mut POOLS: [Option<MemoryPool>; MAX_POOLS] = [None; MAX_POOLS];
+
+fn get_pool(id: u8) -> Result<&'static mut MemoryPool, PoolError> {
+    if id as usize >= MAX_POOLS {
+        return Err(PoolError::NoPool);
+    }
+    unsafe { POOLS[id as usize].as_mut().ok_or(PoolError::NoPool) }
+}
+
+/*
+ * Create a pool of count blocks of block_size bytes, storage comes from
+ * the processes region. Returns the pool id.
+ * */
+pub(crate) fn pool_create(block_size: usize, count: usize) -> Result<u8, PoolError> {
+    // Free blocks hold the next pointer, keep them word aligned
+    let block_size = (block_size.max(4) + 3) & !3;
+    if count == 0 || count > MAX_POOL_BLOCKS {
+        return Err(PoolError::InvalidSize);
+    }
+
+    cortex_m::interrupt::free(|_| unsafe {
+        let id = (0..MAX_POOLS)
+            .find(|&i| POOLS[i].is_none())
+            .ok_or(PoolError::TooManyPools)?;
+
+        let base = process_alloc(block_size * count).ok_or(PoolError::NoMemory)?;
+        POOLS[id] = Some(MemoryPool::new(base, block_size, count));
+        Ok(id as u8)
+    })
+}
+
+/*
+ * Handles on a pool are counted. After the last one is closed its storage
+ * goes back to the processes region, as soon as no block is in use.
+ * */
+pub(crate) fn pool_retain(id: u8) {
+    if let Ok(pool) = get_pool(id) {
+        pool.handles += 1;
+    }
+}
+
+pub(crate) fn pool_release(id: u8) {
+    if let Ok(pool) = get_pool(id) {
+        pool.handles = pool.handles.saturating_sub(1);
+        pool.closed = pool.handles == 0;
+        reclaim(id);
+    }
+}
+
+fn reclaim(id: u8) {
+    let unused = get_pool(id).is_ok_and(|pool| pool.closed && pool.free_count == pool.count);
+    if unused && let Some(pool) = unsafe { POOLS[id as usize].take() } {
+        process_free(pool.base, pool.block_size * pool.count);
+    }
+}
+
+pub(crate) fn pool_free_blocks(id: u8) -> Result<usize, PoolError> {
+    cortex_m::interrupt::free(|_| Ok(get_pool(id)?.free_blocks()))
+}
+
+/*
+ * Take a block without waiting
+ * */
+pub(crate) fn pool_alloc(id: u8) -> Result<*mut u8, PoolError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let owner = CURRENT.unwrap_or(KERNEL_OWNER);
+        get_pool(id)?.alloc(owner).ok_or(PoolError::Exhausted)
+    })
+}
+
+/*
+ * Take a block, parking the calling process until one is released
+ * */
+pub(crate) fn pool_alloc_wait(id: u8) -> Result<*mut u8, PoolError> {
+    loop {
+        let block = cortex_m::interrupt::free(|_| unsafe {
+            let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+            let pool = get_pool(id)?;
+            match pool.alloc(pid) {
+                Some(block) => Ok(Some(block)),
+                None => {
+                    block_current(BlockReason::WaitingForPool(id))?;
+                    Ok::<_, PoolError>(None)
+                }
+            }
+        })?;
+
+        if let Some(block) = block {
+            return Ok(block);
+        }
+        yield_now()?;
+    }
+}
+
+pub(crate) fn pool_free(id: u8, block: *mut u8) -> Result<(), PoolError> {
+    cortex_m::interrupt::free(|_| {
+        let owner = unsafe { CURRENT }.unwrap_or(KERNEL_OWNER);
+        get_pool(id)?.free(block, owner)?;
+        wake_pool_waiter(id);
+        reclaim(id);
+        Ok(())
+    })
+}
+
+// One block came back, one waiter gets to retry
+fn wake_pool_waiter(id: u8) {
+    unsafe {
+        let waiter = (0..MAX_PROCS).find(|&i| matches!(PROCS[i].as_ref().map(|pcb| pcb.state),
+            Some(ProcessState::Blocked(BlockReason::WaitingForPool(pool))) if pool == id));
+        if let Some(pid) = waiter {
+            let _ = unblock(pid as u8);
+        }
+    }
+    wake_pollers(KernelObject::Pool(id));
+}
+
+/*
+ * Give back the blocks of a process that stopped running
+ * */
+pub(crate) fn release_pool_blocks(pid: u8) {
+    for id in 0..MAX_POOLS as u8 {
+        let released = match get_pool(id) {
+            Ok(pool) => pool.release_owner(pid),
+            Err(_) => continue,
+        };
+        for _ in 0..released {
+            wake_pool_waiter(id);
+        }
+        reclaim(id);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{stack_peak, MemoryLayout, MemoryRegion, RegionName, CURRENT, KERNEL_HEAP, PROCESS_MEMORY, PROCS};
+use core::ptr;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RegionUsage {
+    pub name: RegionName,
+    pub region: MemoryRegion,
+    pub used: usize,
+    pub free: usize,
+    pub largest_free: Option<usize>,    // None when the owner cannot tell
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessMemory {
+    pub pid: u8,
+    pub stack_size: usize,
+    pub stack_used: usize,      // At the last context switch
+    pub stack_peak: usize,      // High-water mark since creation
+    pub heap_limit: usize,
+    pub heap_used: usize,
+    pub heap_peak: usize,
+}
+
+/*
+ * Snapshot of the SRAM regions. Per process numbers are read on demand
+ * through processes(), a full table would not fit on a process stack.
+ * */
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryReport {
+    pub regions: [RegionUsage; 6],
+}
+
+impl MemoryReport {
+    pub fn region(&self, name: RegionName) -> Option<&RegionUsage> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    pub fn processes(&self) -> impl Iterator<Item = ProcessMemory> {
+        (0..MAX_PROCS as u8).filter_map(process_memory)
+    }
+}
+
+// Regions handed over as a whole, nothing to walk inside them
+fn reserved(name: RegionName, region: MemoryRegion) -> RegionUsage {
+    RegionUsage { name, region, used: region.size, free: 0, largest_free: Some(0) }
+}
+
+pub fn memory_report() -> MemoryReport {
+    let lay = MemoryLayout::new();
+
+    // Statics sit below the heap, the MSP stack above it
+    let statics = lay.kernel_heap.start - lay.kernel_data.start;
+    let msp_stack = lay.kernel_data.end() - lay.kernel_heap.end();
+    let heap_used = KERNEL_HEAP.used();
+    let heap_free = KERNEL_HEAP.free();
+
+    let kernel_data = RegionUsage {
+        name: RegionName::KernelData,
+        region: lay.kernel_data,
+        used: statics + heap_used + msp_stack,
+        free: heap_free,
+        largest_free: None,
+    };
+    let kernel_heap = RegionUsage {
+        name: RegionName::KernelHeap,
+        region: lay.kernel_heap,
+        used: heap_used,
+        free: heap_free,
+        largest_free: None,
+    };
+
+    let processes = cortex_m::interrupt::free(|_| unsafe {
+        let mem = ptr::addr_of!(PROCESS_MEMORY);
+        // Nothing allocated yet, the whole region is one free block
+        let (free, largest) = if (*mem).is_initialized() {
+            ((*mem).free_bytes(), (*mem).largest_free())
+        } else {
+            (lay.processes.size, lay.processes.size)
+        };
+        RegionUsage {
+            name: RegionName::Processes,
+            region: lay.processes,
+            used: lay.processes.size - free,
+            free,
+            largest_free: Some(largest),
+        }
+    });
+
+    MemoryReport {
+        regions: [
+            kernel_data,
+            kernel_heap,
+            processes,
+            reserved(RegionName::Wifi, lay.wifi),
+            reserved(RegionName::Core0Stack, lay.core0_stack),
+            reserved(RegionName::Core1Stack, lay.core1_stack),
+        ],
+    }
+}
+
+/*
+ * Stack and heap use of one process, None for an empty slot
+ * */
+pub fn process_memory(pid: u8) -> Option<ProcessMemory> {
+    if pid as usize >= MAX_PROCS {
+        return None;
+    }
+    cortex_m::interrupt::free(|_| unsafe {
+        let pcb = PROCS[pid as usize].as_ref()?;
+
+        // The saved sp of the running process is stale, ask the CPU
+        let sp = if CURRENT == Some(pid) {
+            cortex_m::register::psp::read() as usize
+        } else {
+            pcb.sp as usize
+        };
+        let top = pcb.stack_base as usize + pcb.stack_size;
+
+        Some(ProcessMemory {
+            pid,
+            stack_size: pcb.stack_size,
+            stack_used: top.saturating_sub(sp),
+            stack_peak: stack_peak(pcb),
+            heap_limit: pcb.heap_limit,
+            heap_used: pcb.heap_used,
+            heap_peak: pcb.heap_peak,
+        })
+    })
+}
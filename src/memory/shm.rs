@@ -0,0 +1,215 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{process_alloc, process_free, MemoryRegion, CURRENT, PROCS};
+use core::ptr;
+
+pub const MAX_SHM: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum ShmRights {
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShmError {
+    NoRegion,
+    TooManyRegions,
+    InvalidSize,
+    NoMemory,
+    NotMapped,          // Caller holds no grant on the region
+    PermissionDenied,   // Cannot hand out more than the caller holds
+    NoProcess,
+}
+
+/*
+ * A block of the processes region shared by several processes.
+ * Each holder has its own grant, the block goes back to the allocator
+ * when the last grant is dropped.
+ * */
+#[derive(Clone, Copy)]
+pub struct SharedMemory {
+    base: *mut u8,
+    size: usize,
+    grants: [Option<ShmRights>; MAX_PROCS],
+    holders: usize,
+}
+
+impl SharedMemory {
+    fn new(base: *mut u8, size: usize) -> Self {
+        Self { base, size, grants: [None; MAX_PROCS], holders: 0 }
+    }
+
+    fn grant(&mut self, pid: u8, rights: ShmRights) {
+        let grant = &mut self.grants[pid as usize];
+        if grant.is_none() {
+            self.holders += 1;
+        }
+        *grant = Some(rights);
+    }
+
+    // True when this was the last holder
+    fn revoke(&mut self, pid: u8) -> bool {
+        if self.grants[pid as usize].take().is_some() {
+            self.holders -= 1;
+            return self.holders == 0;
+        }
+        false
+    }
+
+    pub fn rights(&self, pid: u8) -> Option<ShmRights> {
+        self.grants.get(pid as usize).copied().flatten()
+    }
+
+    pub fn size(&self) -> usize { self.size }
+
+    pub fn holders(&self) -> usize { self.holders }
+}
+
+/*
+ * What a holder gets back from shm_map. Rights are enforced here,
+ * there is no MPU setup to back them
+ * */
+#[derive(Debug, Clone, Copy)]
+pub struct ShmMapping {
+    pub base: *mut u8,
+    pub size: usize,
+    pub rights: ShmRights,
+}
+
+impl ShmMapping {
+    /// # Safety
+    /// Other holders may write to the region at any time, the caller has
+    /// to agree with them on who touches what.
+    pub unsafe fn as_slice(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.base, self.size) }
+    }
+
+    /// # Safety
+    /// Same as as_slice, None for a read only grant.
+    pub unsafe fn as_mut_slice(&self) -> Option<&'static mut [u8]> {
+        match self.rights {
+            ShmRights::ReadWrite => Some(unsafe { core::slice::from_raw_parts_mut(self.base, self.size) }),
+            ShmRights::ReadOnly => None,
+        }
+    }
+}
+
+pub static mut SHARED_MEMORY: [Option<SharedMemory>; MAX_SHM] = [None; MAX_SHM];
+
+fn get_shm(id: u8) -> Result<&'static mut SharedMemory, ShmError> {
+    if id as usize >= MAX_SHM {
+        return Err(ShmError::NoRegion);
+    }
+    unsafe { SHARED_MEMORY[id as usize].as_mut().ok_or(ShmError::NoRegion) }
+}
+
+fn destroy(id: u8) {
+    unsafe {
+        if let Some(shm) = SHARED_MEMORY[id as usize].take() {
+            process_free(shm.base, shm.size);
+        }
+    }
+}
+
+/*
+ * Allocate a zeroed region of size bytes, the calling process holds it
+ * read-write. Created by the kernel, it lives until granted and released.
+ * */
+pub(crate) fn shm_create(size: usize) -> Result<u8, ShmError> {
+    if size == 0 {
+        return Err(ShmError::InvalidSize);
+    }
+    cortex_m::interrupt::free(|_| unsafe {
+        let id = (0..MAX_SHM)
+            .find(|&i| SHARED_MEMORY[i].is_none())
+            .ok_or(ShmError::TooManyRegions)?;
+
+        let base = process_alloc(size).ok_or(ShmError::NoMemory)?;
+        ptr::write_bytes(base, 0, size);
+
+        let mut shm = SharedMemory::new(base, size);
+        if let Some(pid) = CURRENT {
+            shm.grant(pid, ShmRights::ReadWrite);
+        }
+        SHARED_MEMORY[id] = Some(shm);
+        Ok(id as u8)
+    })
+}
+
+/*
+ * Give pid access to the region. A process can only pass on rights it
+ * holds itself, the kernel can grant anything.
+ * */
+pub(crate) fn shm_grant(id: u8, pid: u8, rights: ShmRights) -> Result<(), ShmError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let shm = get_shm(id)?;
+        if let Some(caller) = CURRENT {
+            let held = shm.rights(caller).ok_or(ShmError::NotMapped)?;
+            if rights > held {
+                return Err(ShmError::PermissionDenied);
+            }
+        }
+        if pid as usize >= MAX_PROCS || PROCS[pid as usize].is_none() {
+            return Err(ShmError::NoProcess);
+        }
+        shm.grant(pid, rights);
+        Ok(())
+    })
+}
+
+pub(crate) fn shm_map(id: u8) -> Result<ShmMapping, ShmError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(ShmError::NotMapped)?;
+        let shm = get_shm(id)?;
+        let rights = shm.rights(pid).ok_or(ShmError::NotMapped)?;
+        Ok(ShmMapping { base: shm.base, size: shm.size, rights })
+    })
+}
+
+/*
+ * Drop the caller's grant, the last holder frees the region
+ * */
+pub(crate) fn shm_release(id: u8) -> Result<(), ShmError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(ShmError::NotMapped)?;
+        let shm = get_shm(id)?;
+        if shm.rights(pid).is_none() {
+            return Err(ShmError::NotMapped);
+        }
+        if shm.revoke(pid) {
+            destroy(id);
+        }
+        Ok(())
+    })
+}
+
+/*
+ * Whether [addr, addr + len) lies inside a region pid holds, with write
+ * access if asked for
+ * */
+pub(crate) fn shm_covers(pid: u8, addr: usize, len: usize, write: bool) -> bool {
+    let range = MemoryRegion { start: addr, size: len };
+    (0..MAX_SHM as u8).filter_map(|id| get_shm(id).ok()).any(|shm| {
+        let region = MemoryRegion { start: shm.base as usize, size: shm.size };
+        match shm.rights(pid) {
+            Some(ShmRights::ReadWrite) => region.encloses(&range),
+            Some(ShmRights::ReadOnly) => !write && region.encloses(&range),
+            None => false,
+        }
+    })
+}
+
+/*
+ * Drop every grant of a process that stopped running
+ * */
+pub(crate) fn release_shm(pid: u8) {
+    for id in 0..MAX_SHM as u8 {
+        let last = match get_shm(id) {
+            Ok(shm) => shm.revoke(pid),
+            Err(_) => continue,
+        };
+        if last {
+            destroy(id);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{discard_process, exit, new_process, Capabilities, ProcessError, RestartSpec, Scheduler, ALLOC_ALIGN, SCHEDULER};
+use core::mem::{self, ManuallyDrop};
+use core::ptr;
+
+pub const DEFAULT_STACK_SIZE: usize = 1024;
+
+/*
+ * Describes a process before it is created
+ *
+ *   ProcessBuilder::new(blink)
+ *       .name("blink")
+ *       .stack_size(1024)
+ *       .spawn()
+ * */
+#[derive(Clone, Copy)]
+pub struct ProcessBuilder<'a> {
+    pub(crate) entry: fn(*mut ()) -> !,
+    pub(crate) arg: *mut (),
+    pub(crate) name: &'a str,
+    pub(crate) stack_size: usize,
+    pub(crate) heap_size: usize,
+    pub(crate) tag: u32,
+    pub(crate) restart: RestartSpec,
+    pub(crate) capabilities: Capabilities,
+    pub(crate) payload: Option<(*const u8, usize)>,   // Bytes copied to the top of the stack
+    pub(crate) payload_drop: Option<unsafe fn(*mut ())>,  // Drops the stack copy when the process is reaped
+    pub(crate) image: Option<(*mut u8, usize)>,       // Loaded app RAM, owned by the process
+}
+
+impl<'a> ProcessBuilder<'a> {
+    pub const fn new(entry: fn(*mut ()) -> !) -> Self {
+        Self {
+            entry,
+            arg: ptr::null_mut(),
+            name: "",
+            stack_size: DEFAULT_STACK_SIZE,
+            heap_size: 0,
+            tag: 0,
+            restart: RestartSpec::NEVER,
+            capabilities: Capabilities::NONE,
+            payload: None,
+            payload_drop: None,
+            image: None,
+        }
+    }
+
+    pub const fn name(mut self, name: &'a str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub const fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    // Hard limit of the process heap grown with sbrk(), 0 for no heap
+    pub const fn heap_size(mut self, size: usize) -> Self {
+        self.heap_size = size;
+        self
+    }
+
+    // Free form value for the application, the kernel never reads it
+    pub const fn tag(mut self, tag: u32) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    pub const fn restart(mut self, spec: RestartSpec) -> Self {
+        self.restart = spec;
+        self
+    }
+
+    // Capped by what the creating process holds itself
+    pub const fn capabilities(mut self, caps: Capabilities) -> Self {
+        self.capabilities = caps;
+        self
+    }
+
+    /// Argument handed to entry in r0.
+    ///
+    /// # Safety
+    /// `arg` must stay valid for as long as the process may run or restart.
+    pub const unsafe fn arg(mut self, arg: *mut ()) -> Self {
+        self.arg = arg;
+        self
+    }
+
+    /*
+     * Create the process and put it on the run queue, returns its pid
+     * */
+    pub fn spawn(&self) -> Result<u8, ProcessError> {
+        let id = new_process(self)?;
+        unsafe {
+            let sched = ptr::addr_of_mut!(SCHEDULER);
+            (*sched).enqueue(id).inspect_err(|_| discard_process(id))?;
+        }
+        Ok(id)
+    }
+
+    /*
+     * Run a closure as the process body, no unsafe needed.
+     * The closure is moved onto the new stack, returning from it is exit(0).
+     * A restart calls the same closure again, with whatever state it kept,
+     * it is only dropped once the process is reaped.
+     * */
+    pub fn spawn_closure<F>(mut self, f: F) -> Result<u8, ProcessError>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        if mem::align_of::<F>() > ALLOC_ALIGN {
+            return Err(ProcessError::InvalidSize);
+        }
+
+        // The stack copy becomes the owner, only drop ours if that failed
+        let f = ManuallyDrop::new(f);
+        self.entry = closure_trampoline::<F>;
+        self.arg = ptr::null_mut();
+        self.payload = Some((&*f as *const F as *const u8, mem::size_of::<F>()));
+        if mem::needs_drop::<F>() {
+            self.payload_drop = Some(drop_closure::<F>);
+        }
+
+        self.spawn().inspect_err(|_| drop(ManuallyDrop::into_inner(f)))
+    }
+
+    /*
+     * Typed argument version of spawn_closure, every (re)start gets a clone
+     * */
+    pub fn spawn_with<T>(self, entry: fn(T), arg: T) -> Result<u8, ProcessError>
+    where
+        T: Clone + Send + 'static,
+    {
+        self.spawn_closure(move || entry(arg.clone()))
+    }
+}
+
+impl Default for ProcessBuilder<'_> {
+    fn default() -> Self {
+        Self::new(no_entry)
+    }
+}
+
+// Entry of a builder that never got one
+fn no_entry(_arg: *mut ()) -> ! {
+    exit(0)
+}
+
+fn closure_trampoline<F: FnMut()>(arg: *mut ()) -> ! {
+    let f = unsafe { &mut *(arg as *mut F) };
+    f();
+    exit(0)
+}
+
+// Captures of a spawned closure, dropped in place on the stack it was copied to
+unsafe fn drop_closure<F>(arg: *mut ()) {
+    unsafe { ptr::drop_in_place(arg as *mut F) }
+}
+
+/*
+ * Start a process running f with the default builder settings
+ * */
+pub fn spawn<F>(f: F) -> Result<u8, ProcessError>
+where
+    F: FnMut() + Send + 'static,
+{
+    ProcessBuilder::default().spawn_closure(f)
+}
+
+pub fn spawn_with<T>(entry: fn(T), arg: T) -> Result<u8, ProcessError>
+where
+    T: Clone + Send + 'static,
+{
+    ProcessBuilder::default().spawn_with(entry, arg)
+}
@@ -0,0 +1,62 @@
+use crate::{get_pcb, CURRENT};
+
+/*
+ * What a process is allowed to ask of the kernel, fixed at creation.
+ * A process can never hand its children more than it holds itself.
+ * */
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    pub const SPAWN: Capabilities = Capabilities(1 << 0);       // Create processes, load apps
+    pub const KILL: Capabilities = Capabilities(1 << 1);        // kill() another process
+    pub const GPIO_BANK0: Capabilities = Capabilities(1 << 2);  // GPIO0-29 not reserved by the kernel
+    pub const WIFI: Capabilities = Capabilities(1 << 3);
+    pub const ALL: Capabilities = Capabilities(0xF);
+
+    pub const fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn bits(self) -> u32 { self.0 }
+}
+
+/*
+ * Whether the caller holds cap. Kernel code, with no current process,
+ * holds everything. A denial is logged, callers turn it into their own
+ * PermissionDenied error.
+ * */
+pub fn has_capability(cap: Capabilities) -> bool {
+    let pid = match unsafe { CURRENT } {
+        Some(pid) => pid,
+        None => return true,
+    };
+    let held = get_pcb(pid).map_or(Capabilities::NONE, |pcb| pcb.capabilities);
+    if held.contains(cap) {
+        return true;
+    }
+    defmt::warn!("pid {=u8}: permission denied, needs {=u32:#x}, holds {=u32:#x}",
+        pid, cap.bits(), held.bits());
+    false
+}
+
+/*
+ * Capabilities a new process ends up with, what was asked for limited
+ * by what its creator holds
+ * */
+pub(crate) fn inherit_capabilities(requested: Capabilities) -> Capabilities {
+    match unsafe { CURRENT }.and_then(get_pcb) {
+        Some(parent) => requested.intersection(parent.capabilities),
+        None => requested,
+    }
+}
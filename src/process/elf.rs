@@ -0,0 +1,369 @@
+use crate::image::{read_u16, read_u32};
+
+/*
+ * ELF32 little endian ARM loader for position independent applications.
+ * The file is expected to be ET_DYN, linked at address 0, with PT_LOAD
+ * segments and a PT_DYNAMIC describing its REL relocations.
+ * */
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_DYN: u16 = 3;
+const EM_ARM: u16 = 40;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const REL_SIZE: usize = 8;
+const SYM_SIZE: usize = 16;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+const DT_NULL: u32 = 0;
+const DT_PLTRELSZ: u32 = 2;
+const DT_SYMTAB: u32 = 6;
+const DT_RELA: u32 = 7;
+const DT_REL: u32 = 17;
+const DT_RELSZ: u32 = 18;
+const DT_PLTREL: u32 = 20;
+const DT_JMPREL: u32 = 23;
+
+const R_ARM_NONE: u32 = 0;
+const R_ARM_ABS32: u32 = 2;
+const R_ARM_GLOB_DAT: u32 = 21;
+const R_ARM_JUMP_SLOT: u32 = 22;
+const R_ARM_RELATIVE: u32 = 23;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElfError {
+    BadMagic,
+    NotElf32LittleEndian,
+    NotArm,
+    NotPositionIndependent,   // Only ET_DYN can be moved to where we have RAM
+    Truncated,
+    NoLoadSegment,
+    BadSegment,
+    BadEntry,
+    BadDynamic,
+    UnsupportedRelocation(u32),
+    UndefinedSymbol(u32),       // Symbol index, there is nothing to link against
+    BadRelocation(u32),         // Offset outside of the loaded image
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub offset: u32,
+    pub vaddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+}
+
+/*
+ * Validated view over an ELF file
+ * */
+#[derive(Debug, Clone, Copy)]
+pub struct ElfImage<'a> {
+    bytes: &'a [u8],
+    entry: u32,
+    phoff: usize,
+    phnum: usize,
+    mem_size: usize,    // Span of all PT_LOAD segments from address 0
+}
+
+impl<'a> ElfImage<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ElfError> {
+        if bytes.len() < 4 || bytes[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if bytes.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if bytes[4] != ELFCLASS32 || bytes[5] != ELFDATA2LSB || bytes[6] != EV_CURRENT {
+            return Err(ElfError::NotElf32LittleEndian);
+        }
+        if read_u16(bytes, 18) != EM_ARM {
+            return Err(ElfError::NotArm);
+        }
+        if read_u16(bytes, 16) != ET_DYN {
+            return Err(ElfError::NotPositionIndependent);
+        }
+
+        let entry = read_u32(bytes, 24);
+        let phoff = read_u32(bytes, 28) as usize;
+        let phentsize = read_u16(bytes, 42) as usize;
+        let phnum = read_u16(bytes, 44) as usize;
+
+        if phentsize != PHDR_SIZE {
+            return Err(ElfError::BadSegment);
+        }
+        if phoff.checked_add(phnum * PHDR_SIZE).is_none_or(|end| end > bytes.len()) {
+            return Err(ElfError::Truncated);
+        }
+
+        let mut elf = Self { bytes, entry, phoff, phnum, mem_size: 0 };
+
+        for i in 0..phnum {
+            let ph = elf.program_header(i);
+            if ph.p_type != PT_LOAD {
+                continue;
+            }
+            if ph.filesz > ph.memsz {
+                return Err(ElfError::BadSegment);
+            }
+            let file_end = ph.offset as u64 + ph.filesz as u64;
+            let mem_end = ph.vaddr as u64 + ph.memsz as u64;
+            if file_end > bytes.len() as u64 || mem_end > u32::MAX as u64 {
+                return Err(ElfError::BadSegment);
+            }
+            elf.mem_size = elf.mem_size.max(mem_end as usize);
+        }
+
+        if elf.mem_size == 0 {
+            return Err(ElfError::NoLoadSegment);
+        }
+        if entry as usize >= elf.mem_size {
+            return Err(ElfError::BadEntry);
+        }
+        Ok(elf)
+    }
+
+    pub fn program_header(&self, idx: usize) -> ProgramHeader {
+        let at = self.phoff + idx * PHDR_SIZE;
+        ProgramHeader {
+            p_type: read_u32(self.bytes, at),
+            offset: read_u32(self.bytes, at + 4),
+            vaddr: read_u32(self.bytes, at + 8),
+            filesz: read_u32(self.bytes, at + 16),
+            memsz: read_u32(self.bytes, at + 20),
+        }
+    }
+
+    // RAM needed to hold every segment, bss included
+    pub fn mem_size(&self) -> usize { self.mem_size }
+
+    /*
+     * Copy .text/.data, zero .bss and apply the dynamic relocations so the
+     * image runs from base. Returns the Thumb entry address.
+     * */
+    pub fn load_into(&self, ram: &mut [u8], base: u32) -> Result<u32, ElfError> {
+        if ram.len() < self.mem_size {
+            return Err(ElfError::BadSegment);
+        }
+        ram[..self.mem_size].fill(0);
+
+        let mut dynamic = None;
+        for i in 0..self.phnum {
+            let ph = self.program_header(i);
+            match ph.p_type {
+                PT_LOAD => {
+                    let src = &self.bytes[ph.offset as usize..(ph.offset + ph.filesz) as usize];
+                    let dst = ph.vaddr as usize;
+                    ram[dst..dst + src.len()].copy_from_slice(src);
+                }
+                PT_DYNAMIC => dynamic = Some(ph),
+                _ => {}
+            }
+        }
+
+        if let Some(ph) = dynamic {
+            let table = segment(ram, ph.vaddr, ph.memsz).ok_or(ElfError::BadDynamic)?;
+            let dyn_info = DynamicInfo::parse(table)?;
+            dyn_info.apply(ram, base)?;
+        }
+
+        Ok(base.wrapping_add(self.entry) | 1)
+    }
+}
+
+// Slice of the loaded image at vaddr, None if it does not fit
+fn segment(ram: &[u8], vaddr: u32, size: u32) -> Option<&[u8]> {
+    let start = vaddr as usize;
+    let end = start.checked_add(size as usize)?;
+    ram.get(start..end)
+}
+
+/*
+ * What we need out of PT_DYNAMIC, all addresses are image relative
+ * */
+#[derive(Default)]
+struct DynamicInfo {
+    rel: u32,
+    relsz: u32,
+    jmprel: u32,
+    pltrelsz: u32,
+    symtab: u32,
+}
+
+impl DynamicInfo {
+    fn parse(table: &[u8]) -> Result<Self, ElfError> {
+        let mut info = DynamicInfo::default();
+        for entry in table.chunks_exact(8) {
+            let tag = read_u32(entry, 0);
+            let val = read_u32(entry, 4);
+            match tag {
+                DT_NULL => break,
+                DT_REL => info.rel = val,
+                DT_RELSZ => info.relsz = val,
+                DT_JMPREL => info.jmprel = val,
+                DT_PLTRELSZ => info.pltrelsz = val,
+                DT_SYMTAB => info.symtab = val,
+                DT_RELA => return Err(ElfError::UnsupportedRelocation(DT_RELA)),
+                DT_PLTREL if val != DT_REL => return Err(ElfError::UnsupportedRelocation(val)),
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+
+    fn apply(&self, ram: &mut [u8], base: u32) -> Result<(), ElfError> {
+        self.apply_table(ram, base, self.rel, self.relsz)?;
+        self.apply_table(ram, base, self.jmprel, self.pltrelsz)
+    }
+
+    fn apply_table(&self, ram: &mut [u8], base: u32, at: u32, size: u32) -> Result<(), ElfError> {
+        if size == 0 {
+            return Ok(());
+        }
+
+        let mut off = 0;
+        while off + REL_SIZE <= size as usize {
+            let rel_at = at.checked_add(off as u32).ok_or(ElfError::BadDynamic)?;
+            let rel = segment(ram, rel_at, REL_SIZE as u32).ok_or(ElfError::BadDynamic)?;
+            let r_offset = read_u32(rel, 0);
+            let r_info = read_u32(rel, 4);
+            self.apply_one(ram, base, r_offset, r_info)?;
+            off += REL_SIZE;
+        }
+        Ok(())
+    }
+
+    fn apply_one(&self, ram: &mut [u8], base: u32, r_offset: u32, r_info: u32) -> Result<(), ElfError> {
+        let r_type = r_info & 0xFF;
+        let at = r_offset as usize;
+        if r_type == R_ARM_NONE {
+            return Ok(());
+        }
+        if !at.is_multiple_of(4) || at + 4 > ram.len() {
+            return Err(ElfError::BadRelocation(r_offset));
+        }
+
+        let addend = read_u32(ram, at);
+        let value = match r_type {
+            R_ARM_RELATIVE => base.wrapping_add(addend),
+            R_ARM_ABS32 => base.wrapping_add(self.symbol(ram, r_info >> 8)?).wrapping_add(addend),
+            R_ARM_GLOB_DAT | R_ARM_JUMP_SLOT => base.wrapping_add(self.symbol(ram, r_info >> 8)?),
+            other => return Err(ElfError::UnsupportedRelocation(other)),
+        };
+        ram[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    // Value of a symbol defined inside the image, nothing else exists to link to
+    fn symbol(&self, ram: &[u8], idx: u32) -> Result<u32, ElfError> {
+        let at = idx.checked_mul(SYM_SIZE as u32)
+            .and_then(|off| self.symtab.checked_add(off))
+            .ok_or(ElfError::BadDynamic)?;
+        let sym = segment(ram, at, SYM_SIZE as u32).ok_or(ElfError::BadDynamic)?;
+        let value = read_u32(sym, 4);
+        let shndx = read_u16(sym, 14);
+        if shndx == 0 {
+            return Err(ElfError::UndefinedSymbol(idx));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built from testdata/app.s, see there for how
+    const PIE: &[u8] = include_bytes!("testdata/pie.elf");
+    const GOT: &[u8] = include_bytes!("testdata/got.elf");
+    const BAD_MAGIC: &[u8] = include_bytes!("testdata/bad_magic.elf");
+    const TRUNCATED_PHDRS: &[u8] = include_bytes!("testdata/truncated_phdrs.elf");
+
+    const BASE: u32 = 0x2002_0000;
+
+    fn load(bytes: &[u8]) -> ([u8; 1024], u32) {
+        let elf = ElfImage::parse(bytes).unwrap();
+        // Garbage where .bss goes, load_into has to clear it
+        let mut ram = [0xEEu8; 1024];
+        let entry = elf.load_into(&mut ram, BASE).unwrap();
+        (ram, entry)
+    }
+
+    #[test]
+    fn parses_valid_pie() {
+        let elf = ElfImage::parse(PIE).unwrap();
+        assert_eq!(elf.mem_size(), 0x1C0);
+        assert_eq!(elf.entry, 0x131);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        assert_eq!(ElfImage::parse(BAD_MAGIC).unwrap_err(), ElfError::BadMagic);
+        assert_eq!(ElfImage::parse(&PIE[..3]).unwrap_err(), ElfError::BadMagic);
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        assert_eq!(ElfImage::parse(TRUNCATED_PHDRS).unwrap_err(), ElfError::Truncated);
+        assert_eq!(ElfImage::parse(&PIE[..EHDR_SIZE - 1]).unwrap_err(), ElfError::Truncated);
+    }
+
+    #[test]
+    fn rejects_wrong_machine_and_type() {
+        let mut bytes = PIE.to_vec();
+        bytes[18] = 3;  // EM_386
+        assert_eq!(ElfImage::parse(&bytes).unwrap_err(), ElfError::NotArm);
+
+        let mut bytes = PIE.to_vec();
+        bytes[16] = 2;  // ET_EXEC
+        assert_eq!(ElfImage::parse(&bytes).unwrap_err(), ElfError::NotPositionIndependent);
+
+        let mut bytes = PIE.to_vec();
+        bytes[4] = 2;   // ELFCLASS64
+        assert_eq!(ElfImage::parse(&bytes).unwrap_err(), ElfError::NotElf32LittleEndian);
+    }
+
+    #[test]
+    fn copies_data_and_zeroes_bss() {
+        let (ram, _) = load(PIE);
+        // message in .data
+        assert_eq!(&ram[0x148..0x14C], b"hi\0\0");
+        // counter in .bss, past the end of the file data
+        assert!(ram[0x1B0..0x1C0].iter().all(|&b| b == 0));
+        // Nothing written past the image
+        assert_eq!(ram[0x1C0], 0xEE);
+    }
+
+    #[test]
+    fn applies_relative_fixups() {
+        let (ram, entry) = load(PIE);
+        assert_eq!(entry, BASE + 0x131);
+        // table[0] = _start, table[1] = message, then the GOT slot of counter
+        assert_eq!(read_u32(&ram, 0x140), BASE + 0x131);
+        assert_eq!(read_u32(&ram, 0x144), BASE + 0x148);
+        assert_eq!(read_u32(&ram, 0x1AC), BASE + 0x1B0);
+    }
+
+    #[test]
+    fn applies_symbol_and_got_fixups() {
+        let (ram, entry) = load(GOT);
+        assert_eq!(entry, BASE + 0x18D);
+        assert_eq!(read_u32(&ram, 0x19C), BASE + 0x18D);   // R_ARM_ABS32 _start
+        assert_eq!(read_u32(&ram, 0x1A0), BASE + 0x1A4);   // R_ARM_RELATIVE message
+        assert_eq!(read_u32(&ram, 0x1F8), BASE + 0x1FC);   // R_ARM_GLOB_DAT counter
+    }
+
+    #[test]
+    fn rejects_ram_too_small() {
+        let elf = ElfImage::parse(PIE).unwrap();
+        let mut ram = [0u8; 0x100];
+        assert_eq!(elf.load_into(&mut ram, BASE).unwrap_err(), ElfError::BadSegment);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{condvar_release, condvar_retain, event_group_release, event_group_retain, get_pcb, mutex_release,
+    mutex_retain, pipe_release, pipe_retain, pool_release, pool_retain, EventError, LockError, PipeError, PoolError, SchedulerError, ShmError, TimerError};
+use core::fmt;
+
+pub const MAX_HANDLES: usize = 8;
+
+/*
+ * Rights a handle carries, checked before the object is touched
+ * */
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rights(u8);
+
+impl Rights {
+    pub const NONE: Rights = Rights(0);
+    pub const READ: Rights = Rights(1 << 0);
+    pub const WRITE: Rights = Rights(1 << 1);
+    pub const TRANSFER: Rights = Rights(1 << 2);    // May be passed to another process
+    pub const ALL: Rights = Rights(0b111);
+
+    pub const fn union(self, other: Rights) -> Rights {
+        Rights(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Rights) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/*
+ * Kernel object a handle refers to, by its id in the owning table
+ * */
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KernelObject {
+    Pool(u8),
+    SharedMemory(u8),
+    EventGroup(u8),
+    Mutex(u8),
+    CondVar(u8),
+    PipeReader(u8),
+    PipeWriter(u8),
+    Timer(u8),
+    Endpoint(u8),       // Pid whose incoming calls it watches
+}
+
+#[derive(Debug)]
+pub enum HandleError {
+    BadHandle,          // Closed, or never opened
+    WrongType,          // Handle refers to another kind of object
+    AccessDenied,       // Handle lacks the rights for the operation
+    TableFull,
+    NoCurrent,
+    NoProcess,
+    Pool(PoolError),
+    Shm(ShmError),
+    Event(EventError),
+    Lock(LockError),
+    Pipe(PipeError),
+    Timer(TimerError),
+    Scheduler(SchedulerError),
+}
+
+impl From<PoolError> for HandleError {
+    fn from(e: PoolError) -> Self {
+        HandleError::Pool(e)
+    }
+}
+
+impl From<ShmError> for HandleError {
+    fn from(e: ShmError) -> Self {
+        HandleError::Shm(e)
+    }
+}
+
+impl From<EventError> for HandleError {
+    fn from(e: EventError) -> Self {
+        HandleError::Event(e)
+    }
+}
+
+impl From<LockError> for HandleError {
+    fn from(e: LockError) -> Self {
+        HandleError::Lock(e)
+    }
+}
+
+impl From<PipeError> for HandleError {
+    fn from(e: PipeError) -> Self {
+        HandleError::Pipe(e)
+    }
+}
+
+impl From<TimerError> for HandleError {
+    fn from(e: TimerError) -> Self {
+        HandleError::Timer(e)
+    }
+}
+
+impl From<SchedulerError> for HandleError {
+    fn from(e: SchedulerError) -> Self {
+        HandleError::Scheduler(e)
+    }
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl core::error::Error for HandleError {}
+
+// What embedded-io users see when a pipe call fails
+impl embedded_io::Error for HandleError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        use embedded_io::ErrorKind;
+        match self {
+            HandleError::Pipe(PipeError::BrokenPipe) => ErrorKind::BrokenPipe,
+            HandleError::Pipe(PipeError::BadBuffer) | HandleError::BadHandle | HandleError::WrongType =>
+                ErrorKind::InvalidInput,
+            HandleError::AccessDenied => ErrorKind::PermissionDenied,
+            HandleError::Pipe(PipeError::NoMemory) => ErrorKind::OutOfMemory,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/*
+ * Index into the handle table of the calling process
+ * */
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Handle(pub u8);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HandleEntry {
+    pub object: KernelObject,
+    pub rights: Rights,
+}
+
+/*
+ * Per process table, lives in the PCB and is emptied when the process
+ * stops running
+ * */
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HandleTable {
+    entries: [Option<HandleEntry>; MAX_HANDLES],
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self { entries: [None; MAX_HANDLES] }
+    }
+
+    pub fn insert(&mut self, object: KernelObject, rights: Rights) -> Result<Handle, HandleError> {
+        let idx = self.entries.iter()
+            .position(|e| e.is_none())
+            .ok_or(HandleError::TableFull)?;
+        self.entries[idx] = Some(HandleEntry { object, rights });
+        Ok(Handle(idx as u8))
+    }
+
+    pub fn get(&self, handle: Handle) -> Result<HandleEntry, HandleError> {
+        self.entries.get(handle.0 as usize)
+            .copied()
+            .flatten()
+            .ok_or(HandleError::BadHandle)
+    }
+
+    /*
+     * Entry of handle if it carries at least rights
+     * */
+    pub fn check(&self, handle: Handle, rights: Rights) -> Result<HandleEntry, HandleError> {
+        let entry = self.get(handle)?;
+        if !entry.rights.contains(rights) {
+            return Err(HandleError::AccessDenied);
+        }
+        Ok(entry)
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Result<HandleEntry, HandleError> {
+        self.entries.get_mut(handle.0 as usize)
+            .and_then(|e| e.take())
+            .ok_or(HandleError::BadHandle)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.iter().all(|e| e.is_some())
+    }
+
+    pub fn free_slots(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_none()).count()
+    }
+
+    // Some other handle still refers to object
+    pub fn refers_to(&self, object: KernelObject) -> bool {
+        self.entries.iter().flatten().any(|e| e.object == object)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = [None; MAX_HANDLES];
+    }
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+ * A handle on object was opened. Objects that count their handles go
+ * away with the last one, the rest are looked after by their own modules.
+ * */
+pub(crate) fn retain_object(object: KernelObject) {
+    match object {
+        KernelObject::Pool(id) => pool_retain(id),
+        KernelObject::EventGroup(id) => event_group_retain(id),
+        KernelObject::Mutex(id) => mutex_retain(id),
+        KernelObject::CondVar(id) => condvar_retain(id),
+        KernelObject::PipeReader(_) | KernelObject::PipeWriter(_) => pipe_retain(object),
+        _ => {}
+    }
+}
+
+/*
+ * A handle holder had on object was closed
+ * */
+pub(crate) fn release_object(object: KernelObject, holder: u8) {
+    match object {
+        KernelObject::Pool(id) => pool_release(id),
+        KernelObject::EventGroup(id) => event_group_release(id),
+        KernelObject::Mutex(id) => mutex_release(id),
+        KernelObject::CondVar(id) => condvar_release(id),
+        KernelObject::PipeReader(_) | KernelObject::PipeWriter(_) => pipe_release(object, holder),
+        _ => {}
+    }
+}
+
+/*
+ * Forget every handle of a process that stopped running, the objects
+ * that count handles are told here
+ * */
+pub(crate) fn release_handles(pid: u8) {
+    if let Some(pcb) = get_pcb(pid) {
+        let entries = pcb.handles.entries;
+        pcb.handles.clear();
+        for entry in entries.iter().flatten() {
+            release_object(entry.object, pid);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::ALLOC_ALIGN;
+
+/*
+ * Application image format, as stored in the APPS flash partition.
+ * All fields are little endian, images follow each other 4 byte aligned.
+ *
+ * Offset  Size  Field
+ *      0     4  magic "JPAP"
+ *      4     2  version
+ *      6     2  header size
+ *      8     4  entry offset, from the load address
+ *     12     4  image size, code and data copied to RAM
+ *     16     4  relocation count
+ *     20     4  stack size, a multiple of 8
+ *     24     4  RAM size, image plus zeroed bss
+ *     28     4  CRC32 of the image and relocation table
+ *     32    16  name, zero padded
+ *     48        image, then relocation table
+ *
+ * Each relocation is a u32 offset into the image of a word holding an
+ * address relative to the load address, the loader adds the load address.
+ * */
+
+pub const APP_MAGIC: [u8; 4] = *b"JPAP";
+pub const APP_VERSION: u16 = 1;
+pub const APP_HEADER_SIZE: usize = 48;
+pub const APP_NAME_LEN: usize = 16;
+
+// Both come out of the 146K processes region, leave room for other processes
+pub const APP_MAX_STACK: u32 = 32 * 1024;
+pub const APP_MAX_RAM: u32 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    BadCrc,
+    BadEntry,
+    BadRelocation(u32),     // Offset that points outside the image
+    BadLayout,              // Sizes do not add up
+    TooLarge,               // Stack or RAM past APP_MAX_STACK/APP_MAX_RAM
+    MisalignedStack,        // Stack size not a multiple of ALLOC_ALIGN
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AppHeader {
+    pub version: u16,
+    pub header_size: u16,
+    pub entry_offset: u32,
+    pub image_size: u32,
+    pub reloc_count: u32,
+    pub stack_size: u32,
+    pub ram_size: u32,
+    pub crc: u32,
+    pub name: [u8; APP_NAME_LEN],
+}
+
+pub(crate) fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+impl AppHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ImageError> {
+        if bytes.len() < 4 || bytes[..4] != APP_MAGIC {
+            return Err(ImageError::BadMagic);
+        }
+        if bytes.len() < APP_HEADER_SIZE {
+            return Err(ImageError::Truncated);
+        }
+
+        let version = read_u16(bytes, 4);
+        if version != APP_VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+
+        let mut name = [0u8; APP_NAME_LEN];
+        name.copy_from_slice(&bytes[32..32 + APP_NAME_LEN]);
+
+        let header = Self {
+            version,
+            header_size: read_u16(bytes, 6),
+            entry_offset: read_u32(bytes, 8),
+            image_size: read_u32(bytes, 12),
+            reloc_count: read_u32(bytes, 16),
+            stack_size: read_u32(bytes, 20),
+            ram_size: read_u32(bytes, 24),
+            crc: read_u32(bytes, 28),
+            name,
+        };
+
+        // Sizes come from flash, keep the arithmetic on them from overflowing
+        let total = header.header_size as u64 + header.image_size as u64
+            + header.reloc_count as u64 * 4;
+        if (header.header_size as usize) < APP_HEADER_SIZE
+            || header.ram_size < header.image_size
+            || total > u32::MAX as u64 {
+            return Err(ImageError::BadLayout);
+        }
+        if header.stack_size > APP_MAX_STACK || header.ram_size > APP_MAX_RAM {
+            return Err(ImageError::TooLarge);
+        }
+        // The initial frame is written a word at a time down from the top
+        if !(header.stack_size as usize).is_multiple_of(ALLOC_ALIGN) {
+            return Err(ImageError::MisalignedStack);
+        }
+        // Thumb entry has to land on an instruction inside the image
+        if header.entry_offset >= header.image_size || header.entry_offset & 1 != 0 {
+            return Err(ImageError::BadEntry);
+        }
+        Ok(header)
+    }
+
+    // Bytes covered by the CRC, image plus relocation table
+    pub fn payload_len(&self) -> usize {
+        self.image_size as usize + self.reloc_count as usize * 4
+    }
+
+    // Header, payload and padding up to the next image
+    pub fn total_len(&self) -> usize {
+        (self.header_size as usize + self.payload_len() + 3) & !3
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(APP_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+/*
+ * A header checked against its payload
+ * */
+#[derive(Debug, Clone, Copy)]
+pub struct AppImage<'a> {
+    pub header: AppHeader,
+    pub image: &'a [u8],
+    pub relocs: &'a [u8],
+}
+
+impl<'a> AppImage<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ImageError> {
+        let header = AppHeader::parse(bytes)?;
+
+        let start = header.header_size as usize;
+        let end = start + header.payload_len();
+        if bytes.len() < end {
+            return Err(ImageError::Truncated);
+        }
+
+        let payload = &bytes[start..end];
+        if crc32(payload) != header.crc {
+            return Err(ImageError::BadCrc);
+        }
+
+        let (image, relocs) = payload.split_at(header.image_size as usize);
+        Ok(Self { header, image, relocs })
+    }
+
+    /*
+     * Copy the image into ram, zero the bss and relocate it for base,
+     * the address ram will be executed from
+     * */
+    pub fn load_into(&self, ram: &mut [u8], base: u32) -> Result<(), ImageError> {
+        if ram.len() < self.header.ram_size as usize {
+            return Err(ImageError::BadLayout);
+        }
+
+        let (image, bss) = ram.split_at_mut(self.image.len());
+        image.copy_from_slice(self.image);
+        bss.fill(0);
+
+        relocate(image, self.relocs, base)
+    }
+}
+
+/*
+ * Add base to every word listed in relocs, a table of u32 offsets
+ * */
+pub fn relocate(image: &mut [u8], relocs: &[u8], base: u32) -> Result<(), ImageError> {
+    for entry in relocs.chunks_exact(4) {
+        let offset = read_u32(entry, 0);
+        let at = offset as usize;
+        if !at.is_multiple_of(4) || at + 4 > image.len() {
+            return Err(ImageError::BadRelocation(offset));
+        }
+
+        let value = read_u32(image, at).wrapping_add(base);
+        image[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    Ok(())
+}
+
+/*
+ * CRC-32 (IEEE 802.3), bitwise since flash is cheaper than RAM tables here
+ * */
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    const STACK: u32 = 1024;
+
+    // Header for image and relocs, with a matching CRC
+    fn build(image: &[u8], relocs: &[u32], stack_size: u32, ram_size: u32) -> Vec<u8> {
+        let mut payload = image.to_vec();
+        for r in relocs {
+            payload.extend_from_slice(&r.to_le_bytes());
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&APP_MAGIC);
+        bytes.extend_from_slice(&APP_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(APP_HEADER_SIZE as u16).to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(relocs.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&stack_size.to_le_bytes());
+        bytes.extend_from_slice(&ram_size.to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        let mut name = [0u8; APP_NAME_LEN];
+        name[..5].copy_from_slice(b"blink");
+        bytes.extend_from_slice(&name);
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    // Two words of code, one pointer at offset 8 relative to the load address
+    fn sample() -> Vec<u8> {
+        let mut image = [0u8; 12];
+        image[8..].copy_from_slice(&4u32.to_le_bytes());
+        build(&image, &[8], STACK, 32)
+    }
+
+    #[test]
+    fn parses_valid_image() {
+        let bytes = sample();
+        let app = AppImage::parse(&bytes).unwrap();
+        assert_eq!(app.header.name(), "blink");
+        assert_eq!(app.header.stack_size, STACK);
+        assert_eq!(app.image.len(), 12);
+        assert_eq!(app.relocs.len(), 4);
+        assert_eq!(app.header.total_len(), bytes.len().next_multiple_of(4));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = sample();
+        bytes[0] = b'X';
+        assert_eq!(AppHeader::parse(&bytes).unwrap_err(), ImageError::BadMagic);
+        assert_eq!(AppHeader::parse(&[]).unwrap_err(), ImageError::BadMagic);
+    }
+
+    #[test]
+    fn rejects_bad_version() {
+        let mut bytes = sample();
+        bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(AppHeader::parse(&bytes).unwrap_err(), ImageError::UnsupportedVersion(2));
+    }
+
+    #[test]
+    fn rejects_crc_mismatch() {
+        let mut bytes = sample();
+        bytes[APP_HEADER_SIZE] ^= 0xFF;
+        assert_eq!(AppImage::parse(&bytes).unwrap_err(), ImageError::BadCrc);
+    }
+
+    #[test]
+    fn rejects_truncated_header_and_payload() {
+        let bytes = sample();
+        assert_eq!(AppHeader::parse(&bytes[..APP_HEADER_SIZE - 1]).unwrap_err(), ImageError::Truncated);
+        // The header alone still parses, the payload is what is missing
+        assert!(AppHeader::parse(&bytes[..bytes.len() - 1]).is_ok());
+        assert_eq!(AppImage::parse(&bytes[..bytes.len() - 1]).unwrap_err(), ImageError::Truncated);
+    }
+
+    #[test]
+    fn rejects_oversized_stack_and_ram() {
+        let image = [0u8; 8];
+        let bytes = build(&image, &[], APP_MAX_STACK + 1, 8);
+        assert_eq!(AppHeader::parse(&bytes).unwrap_err(), ImageError::TooLarge);
+        let bytes = build(&image, &[], STACK, APP_MAX_RAM + 1);
+        assert_eq!(AppHeader::parse(&bytes).unwrap_err(), ImageError::TooLarge);
+        let bytes = build(&image, &[], APP_MAX_STACK, APP_MAX_RAM);
+        assert!(AppHeader::parse(&bytes).is_ok());
+    }
+
+    #[test]
+    fn rejects_misaligned_stack() {
+        let image = [0u8; 8];
+        for stack in [STACK + 1, STACK + 4, STACK - 2] {
+            let bytes = build(&image, &[], stack, 8);
+            assert_eq!(AppHeader::parse(&bytes).unwrap_err(), ImageError::MisalignedStack);
+        }
+    }
+
+    #[test]
+    fn rejects_ram_smaller_than_image() {
+        let bytes = build(&[0u8; 16], &[], STACK, 8);
+        assert_eq!(AppHeader::parse(&bytes).unwrap_err(), ImageError::BadLayout);
+    }
+
+    #[test]
+    fn loads_zeroes_bss_and_relocates() {
+        let bytes = sample();
+        let app = AppImage::parse(&bytes).unwrap();
+        let mut ram = [0xEEu8; 32];
+        app.load_into(&mut ram, 0x2002_0000).unwrap();
+        assert_eq!(read_u32(&ram, 8), 0x2002_0004);
+        assert!(ram[12..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rejects_relocation_outside_image() {
+        let mut image = [0u8; 8];
+        assert_eq!(relocate(&mut image, &8u32.to_le_bytes(), 0), Err(ImageError::BadRelocation(8)));
+        assert_eq!(relocate(&mut image, &2u32.to_le_bytes(), 0), Err(ImageError::BadRelocation(2)));
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
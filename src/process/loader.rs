@@ -1,20 +1,43 @@
-use crate::{process::*, Scheduler, PROCS, SCHEDULER};
-use crate::layout::MemoryLayout;
+use crate::{process::*, get_time_us, SchedulerError, release_futex, release_ipc, release_mutexes, release_pool_blocks, release_services, release_shm, release_timers, IpcBuffers, SignalState, MemoryLayout, process_alloc, process_free, unblock, CURRENT, PROCS};
+use crate::scheduler::MAX_PROCS;
+use crate::{ALLOC_ALIGN, KERNEL_HEAP};
 use core::ptr;
 
 use core::result::Result;
 use core::result::Result::{Ok, Err};
 
 
-static mut NEXT_FREE: usize = 0; 
-static mut ID: u8 = 0; 
-
 #[derive(Debug)]
 pub enum ProcessError {
     NoMemory, 
     InvalidSize, 
+    TooManyProcesses,
+    HeapLimit,          // sbrk() past the heap size chosen at creation
+    NameTooLong,
+    Scheduler(SchedulerError),  // Created but could not be queued to run
+    PermissionDenied,   // Caller lacks Capabilities::SPAWN
+    BadImage(ImageError),
+    BadElf(ElfError),
 } 
 
+impl From<ImageError> for ProcessError {
+    fn from(e: ImageError) -> Self {
+        ProcessError::BadImage(e)
+    }
+}
+
+impl From<SchedulerError> for ProcessError {
+    fn from(e: SchedulerError) -> Self {
+        ProcessError::Scheduler(e)
+    }
+}
+
+impl From<ElfError> for ProcessError {
+    fn from(e: ElfError) -> Self {
+        ProcessError::BadElf(e)
+    }
+}
+
 /*
  * Return the start of the stack, given size
  * */
@@ -22,19 +45,28 @@ fn allocate_stack(size: usize) -> Result<*mut u8, ProcessError> {
     if size == 0 {
         return Err(ProcessError::InvalidSize);
     }
-    let region = MemoryLayout::new(); 
+    process_alloc(size).ok_or(ProcessError::NoMemory)
+}
+
+/*
+ * First empty PCB slot, its index becomes the pid. Only good until the
+ * critical section it is called from ends.
+ * */
+fn free_pid() -> Result<u8, ProcessError> {
     unsafe {
-        if NEXT_FREE + size > region.processes.size {
-            return Err(ProcessError::NoMemory);
-        }
-        else {
-            let ptr: *mut u8 = (NEXT_FREE + region.processes.start) as *mut u8;
-            NEXT_FREE += size; 
-            return Ok(ptr); 
-        }
+        (0..MAX_PROCS)
+            .find(|&i| PROCS[i].is_none())
+            .map(|i| i as u8)
+            .ok_or(ProcessError::TooManyProcesses)
     }
 }
 
+// Exception frame plus r4-r11, what setup_initial_stack pushes
+const INITIAL_FRAME_SIZE: usize = 16 * 4;
+
+// Fresh stacks are filled with this so the deepest use can be found later
+pub const STACK_PAINT: u8 = 0xA5;
+
 fn process_panic() -> ! {
     loop {}
 }
@@ -57,7 +89,7 @@ fn process_panic() -> ! {
  * R4
  * 
  * */
-unsafe fn setup_initial_stack(stack_base: *mut u8, 
+pub(crate) unsafe fn setup_initial_stack(stack_base: *mut u8, 
     stack_size: usize, entry: fn(*mut ()) -> !, arg: *mut()) -> *mut u32 {
 
     // SP pointing to top of the stack 
@@ -65,6 +97,9 @@ unsafe fn setup_initial_stack(stack_base: *mut u8,
 
     let xpsr_value: u32 = 0 | 1 << 24;
     unsafe {
+        // Top of the stack is one past the block, keeps the frame 8 byte aligned
+        sp = sp.offset(-1);
+
         // xPSR 
         *sp = xpsr_value;
         sp = sp.offset(-1);
@@ -102,24 +137,314 @@ unsafe fn setup_initial_stack(stack_base: *mut u8,
 }
 
 
-pub unsafe fn create_process(stack_size: usize, 
-    entry: fn(* mut()) -> !, parg: *mut ()) -> Result<u8, ProcessError> {
-    let stack_start = allocate_stack(stack_size)?;
+/*
+ * Build the PCB for a new process without making it runnable
+ * */
+pub(crate) fn new_process(builder: &ProcessBuilder) -> Result<u8, ProcessError> {
+    if builder.name.len() > PROCESS_NAME_LEN {
+        return Err(ProcessError::NameTooLong);
+    }
+    if !has_capability(Capabilities::SPAWN) {
+        return Err(ProcessError::PermissionDenied);
+    }
+    // The initial frame is written a word at a time down from the top, keep that 8 byte aligned
+    let stack_size = builder.stack_size.checked_next_multiple_of(ALLOC_ALIGN).ok_or(ProcessError::InvalidSize)?;
+
+    // Spawned closures live at the very top of the stack, above the first frame
+    let (payload, payload_len) = builder.payload.unwrap_or((ptr::null(), 0));
+    let payload_size = (payload_len + ALLOC_ALIGN - 1) & !(ALLOC_ALIGN - 1);
+    if payload_size + INITIAL_FRAME_SIZE > stack_size {
+        return Err(ProcessError::InvalidSize);
+    }
+
+    // Heap sits right below the stack, both come out of one block
+    let heap_size = (builder.heap_size + ALLOC_ALIGN - 1) & !(ALLOC_ALIGN - 1);
+    let block = allocate_stack(heap_size + stack_size)?;
+    let stack_start = unsafe { block.add(heap_size) };
+
+    let mut name = [0u8; PROCESS_NAME_LEN];
+    name[..builder.name.len()].copy_from_slice(builder.name.as_bytes());
 
     unsafe {
-        let sp = setup_initial_stack(stack_start, stack_size, entry, parg);
-        let id: u8 = ID; 
-        let pcb = PCB {
+        let mut arg = builder.arg;
+        if builder.payload.is_some() {
+            let dst = stack_start.add(stack_size - payload_size);
+            ptr::copy_nonoverlapping(payload, dst, payload_len);
+            arg = dst as *mut ();
+        }
+
+        ptr::write_bytes(stack_start, STACK_PAINT, stack_size - payload_size);
+        let sp = setup_initial_stack(stack_start, stack_size - payload_size, builder.entry, arg);
+        let mut pcb = PCB {
             sp: sp,
-            pid: id, 
+            pid: 0, 
             state: ProcessState::Ready, 
             stack_base: stack_start, 
             stack_size: stack_size, 
+            entry: builder.entry,
+            arg,
+            payload_size,
+            payload_drop: builder.payload_drop,
+            image_base: builder.image.map_or(ptr::null_mut(), |(base, _)| base),
+            image_size: builder.image.map_or(0, |(_, size)| size),
+            heap_base: block,
+            heap_limit: heap_size,
+            heap_used: 0,
+            heap_peak: 0,
+            supervisor: Supervisor::new(builder.restart),
+            parent: CURRENT,
+            name,
+            name_len: builder.name.len() as u8,
+            created_at: get_time_us(),
+            tag: builder.tag,
+            handles: HandleTable::new(),
+            capabilities: inherit_capabilities(builder.capabilities),
+            deadline: None,
+            wake_value: None,
+            futex_next: None,
+            ipc: IpcBuffers::EMPTY,
+            signals: SignalState::EMPTY,
         };
-        PROCS[id as usize] = Some(pcb); 
-        let sched = ptr::addr_of_mut!(SCHEDULER); 
-        (*sched).enqueue(id).unwrap();
-        ID += 1; 
-        Ok(id)
+
+        // Pick the pid and take its slot in one go, or another spawn could get it too
+        cortex_m::interrupt::free(|_| {
+            let id = free_pid()?;
+            pcb.pid = id;
+            PROCS[id as usize] = Some(pcb);
+            Ok(id)
+        }).inspect_err(|_| process_free(block, heap_size + stack_size))
+    }
+}
+
+/*
+ * Undo new_process for a process that never ran. An image or closure it
+ * was given stays with the caller.
+ * */
+pub(crate) fn discard_process(pid: u8) {
+    let pcb = cortex_m::interrupt::free(|_| unsafe { PROCS[pid as usize].take() });
+    if let Some(pcb) = pcb {
+        process_free(pcb.heap_base, pcb.heap_limit + pcb.stack_size);
+    }
+}
+
+/*
+ * Pid of the first live process with this name
+ * */
+pub fn find_process(name: &str) -> Option<u8> {
+    unsafe {
+        (0..MAX_PROCS)
+            .find(|&i| matches!(PROCS[i].as_ref(), Some(pcb) if pcb.name() == name))
+            .map(|i| i as u8)
+    }
+}
+
+/*
+ * Kernel resources held by a running process, dropped as soon as it stops
+ * running, whether it then restarts or stays a zombie
+ * */
+pub(crate) fn release_resources(pid: u8) {
+    release_pool_blocks(pid);
+    release_shm(pid);
+    release_mutexes(pid);
+    release_futex(pid);
+    release_ipc(pid);
+    release_services(pid);
+    release_timers(pid);
+    release_handles(pid);
+}
+
+/*
+ * Deepest the stack has been since the process was created, found by
+ * looking for the first byte that lost its paint
+ * */
+pub fn stack_peak(pcb: &PCB) -> usize {
+    let stack = unsafe { core::slice::from_raw_parts(pcb.stack_base, pcb.stack_size) };
+    let untouched = stack.iter().take_while(|&&b| b == STACK_PAINT).count();
+    pcb.stack_size - untouched
+}
+
+/*
+ * Release everything a zombie holds, its pid becomes free for reuse
+ * */
+pub(crate) fn reap(pid: u8) {
+    unsafe {
+        if let Some(pcb) = PROCS[pid as usize].take() {
+            // A failure it left behind must not follow the pid to its next owner
+            KERNEL_HEAP.take_failed_for(pid);
+            // Dropped before the stack holding it goes
+            if let Some(drop_payload) = pcb.payload_drop {
+                drop_payload(pcb.arg);
+            }
+            process_free(pcb.heap_base, pcb.heap_limit + pcb.stack_size);
+            if !pcb.image_base.is_null() {
+                process_free(pcb.image_base, pcb.image_size);
+            }
+        }
+    }
+}
+
+/*
+ * A process has become a zombie for good. Wake a parent blocked in wait(),
+ * orphans are reaped straight away since nobody can collect them,
+ * and our own children lose their parent.
+ * */
+pub(crate) fn process_exited(pid: u8) {
+    unsafe {
+        for i in 0..MAX_PROCS as u8 {
+            let child = match PROCS[i as usize].as_mut() {
+                Some(child) if child.parent == Some(pid) => child,
+                _ => continue,
+            };
+            child.parent = None;
+            if let ProcessState::Zombie(_) = child.state {
+                reap(i);
+            }
+        }
+
+        let parent = PROCS[pid as usize].as_ref().and_then(|pcb| pcb.parent);
+        let parent_pcb = parent.and_then(|ppid| PROCS[ppid as usize].as_ref());
+        match parent_pcb.map(|pcb| pcb.state) {
+            Some(ProcessState::Blocked(BlockReason::WaitingForChild(child))) if child == pid => {
+                let _ = unblock(parent.unwrap());
+            }
+            Some(_) => {},
+            None => reap(pid),
+        }
+    }
+}
+
+/*
+ * Load one application image into RAM from the processes region and
+ * start it, the RAM is given back when the process is reaped
+ * */
+pub fn load_app(bytes: &[u8]) -> Result<u8, ProcessError> {
+    let app = AppImage::parse(bytes)?;
+    let ram_size = app.header.ram_size as usize;
+    let ram = process_alloc(ram_size).ok_or(ProcessError::NoMemory)?;
+
+    unsafe {
+        let ram_slice = core::slice::from_raw_parts_mut(ram, ram_size);
+        if let Err(e) = app.load_into(ram_slice, ram as u32) {
+            process_free(ram, ram_size);
+            return Err(e.into());
+        }
+
+        let entry_addr = (ram as usize + app.header.entry_offset as usize) | 1;
+        let entry: fn(*mut ()) -> ! = core::mem::transmute(entry_addr);
+
+        let mut builder = ProcessBuilder::new(entry)
+            .name(app.header.name())
+            .stack_size(app.header.stack_size as usize);
+        builder.image = Some((ram, ram_size));
+
+        builder.spawn().inspect_err(|_| process_free(ram, ram_size))
+    }
+}
+
+/*
+ * Load a position independent ELF into the processes region and start it.
+ * ELF carries no stack size, so the caller picks one.
+ * */
+pub fn load_elf(bytes: &[u8], name: &str, stack_size: usize) -> Result<u8, ProcessError> {
+    let elf = ElfImage::parse(bytes)?;
+    let ram_size = elf.mem_size();
+    let ram = process_alloc(ram_size).ok_or(ProcessError::NoMemory)?;
+
+    unsafe {
+        let ram_slice = core::slice::from_raw_parts_mut(ram, ram_size);
+        let entry_addr = match elf.load_into(ram_slice, ram as u32) {
+            Ok(entry_addr) => entry_addr,
+            Err(e) => {
+                process_free(ram, ram_size);
+                return Err(e.into());
+            }
+        };
+        let entry: fn(*mut ()) -> ! = core::mem::transmute(entry_addr as usize);
+
+        let mut builder = ProcessBuilder::new(entry)
+            .name(name)
+            .stack_size(stack_size);
+        builder.image = Some((ram, ram_size));
+
+        builder.spawn().inspect_err(|_| process_free(ram, ram_size))
+    }
+}
+
+/*
+ * Walk the APPS flash partition and start every valid image.
+ * Scanning stops at the first slot without a header, erased flash included.
+ * Returns how many applications were started.
+ * */
+pub fn load_apps() -> usize {
+    let apps = MemoryLayout::new().apps;
+    let partition = unsafe { core::slice::from_raw_parts(apps.start as *const u8, apps.size) };
+
+    let mut offset = 0;
+    let mut loaded = 0;
+    while offset < partition.len() {
+        let bytes = &partition[offset..];
+        let header = match AppHeader::parse(bytes) {
+            Ok(header) => header,
+            Err(_) => break,
+        };
+
+        // A broken image is skipped, the header still tells us where the next starts
+        if load_app(bytes).is_ok() {
+            loaded += 1;
+        }
+        offset += header.total_len();
+    }
+    loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::kernel;
+    use std::sync::Arc;
+
+    fn body(_arg: *mut ()) -> ! {
+        loop {}
+    }
+
+    #[test]
+    fn stack_size_is_rounded_up() {
+        let _kernel = kernel();
+        for (asked, given) in [(1001, 1008), (1020, 1024), (1024, 1024)] {
+            let pid = new_process(&ProcessBuilder::new(body).stack_size(asked)).unwrap();
+            let pcb = unsafe { PROCS[pid as usize].as_ref() }.unwrap();
+            assert_eq!(pcb.stack_size, given);
+            assert_eq!((pcb.stack_base as usize + pcb.stack_size) % ALLOC_ALIGN, 0);
+            assert_eq!(pcb.sp as usize % ALLOC_ALIGN, 0);
+        }
+    }
+
+    #[test]
+    fn rejects_stack_size_that_cannot_be_rounded() {
+        let _kernel = kernel();
+        let err = new_process(&ProcessBuilder::new(body).stack_size(usize::MAX)).unwrap_err();
+        assert!(matches!(err, ProcessError::InvalidSize));
+    }
+
+    #[test]
+    fn reap_drops_spawned_closure() {
+        let _kernel = kernel();
+        let held = Arc::new(());
+        let captured = held.clone();
+        let pid = ProcessBuilder::new(body).spawn_closure(move || { let _ = &captured; }).unwrap();
+        assert_eq!(Arc::strong_count(&held), 2);
+
+        reap(pid);
+        assert_eq!(Arc::strong_count(&held), 1);
+    }
+
+    #[test]
+    fn failed_spawn_drops_closure_once() {
+        let _kernel = kernel();
+        let held = Arc::new(());
+        let captured = held.clone();
+        let err = ProcessBuilder::new(body).stack_size(16).spawn_closure(move || { let _ = &captured; });
+        assert!(matches!(err, Err(ProcessError::InvalidSize)));
+        assert_eq!(Arc::strong_count(&held), 1);
     }
 }
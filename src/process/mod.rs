@@ -1,5 +1,17 @@
 pub mod pcb; 
 pub mod loader;
+pub mod supervisor;
+pub mod builder;
+pub mod image;
+pub mod elf;
+pub mod handle;
+pub mod capability;
 
 pub use pcb::*;
 pub use loader::*;
+pub use supervisor::*;
+pub use builder::*;
+pub use image::*;
+pub use elf::*;
+pub use handle::*;
+pub use capability::*;
@@ -1,19 +1,49 @@
 use core::clone::Clone;
 use core::marker::Copy;
 
+use crate::{Capabilities, HandleTable, IpcBuffers, SignalState, Supervisor};
+
+pub const PROCESS_NAME_LEN: usize = 16;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub enum ProcessState {
     Ready, 
     Running, 
     Blocked(BlockReason),
+    Zombie(ExitReason),     // Terminated and not restarted by its supervisor
 }
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub enum BlockReason {
     Sleeping(u64),   // wake_time
+    RestartBackoff(u64),    // wake_time, supervisor delay before a restart
     WaitingForWifi, 
+    WaitingForChild(u8),    // pid passed to wait()
+    WaitingForPool(u8),     // Pool id with no free block
+    WaitingForEvents { group: u8, mask: u32, all: bool, clear: bool },
+    WaitingForMutex { mutex: u8, ticket: u32 },     // Lowest ticket gets the mutex next
+    WaitingForCondVar { cv: u8, mutex: u8, ticket: u32 },
+    WaitingForFutex(usize),     // User address
+    SendBlocked { server: u8, ticket: u32 },    // call() not yet received
+    ReplyBlocked(u8),       // Received by this server, waiting for reply()
+    Receiving,              // Server in receive() with no caller
+    WaitingForService(u32), // Hash of the service name
+    WaitingForPipe { pipe: u8, write: bool },   // Writers wait for room, readers for data
+    Polling,                // In poll(), woken by any object it holds a handle to
+    WaitingForTimer,        // Timer task with nothing expired
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitReason {
+    Exited(i32),    // Code passed to exit()
+    Faulted,        // HardFault raised while the process was running
+    OutOfMemory,    // Kernel heap could not satisfy an allocation
+    Panicked,       // Rust panic in the process
+    Killed,         // Terminated by kill()
+    Signaled(u8),   // Signal whose default action is to terminate
 }
 
 
@@ -25,5 +55,34 @@ pub struct PCB {
     pub state: ProcessState, 
     pub stack_base: *mut u8,    // Where stack allocation starts 
     pub stack_size: usize,      // Stack size, native size 
+    pub entry: fn(*mut ()) -> !,    // Kept so the supervisor can restart it
+    pub arg: *mut (),
+    pub payload_size: usize,    // Bytes at the top of the stack holding a spawned closure
+    pub payload_drop: Option<unsafe fn(*mut ())>,   // Run on arg when the process is reaped
+    pub image_base: *mut u8,    // RAM of a loaded application, null for built in code
+    pub image_size: usize,
+    pub heap_base: *mut u8,     // Start of the block holding heap then stack
+    pub heap_limit: usize,      // Heap bytes reserved at creation
+    pub heap_used: usize,       // Current break, from heap_base
+    pub heap_peak: usize,       // Highest break seen
+    pub supervisor: Supervisor,
+    pub parent: Option<u8>,     // Process that created us, None for the kernel
+    pub name: [u8; PROCESS_NAME_LEN],
+    pub name_len: u8,
+    pub created_at: u64,        // Time (us) of creation
+    pub tag: u32,               // User defined, set through ProcessBuilder
+    pub handles: HandleTable,   // Kernel objects the process can reach
+    pub capabilities: Capabilities,
+    pub deadline: Option<u64>,  // Timeout (us) of the blocking call in progress
+    pub wake_value: Option<u32>,    // Left by whoever unblocked us, see unblock_with
+    pub futex_next: Option<u8>, // Next waiter in the same futex bucket
+    pub ipc: IpcBuffers,        // Message buffers of a call() in progress
+    pub signals: SignalState,   // Pending, blocked and handled signals
+}
+
+impl PCB {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or("")
+    }
 }
 
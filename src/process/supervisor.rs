@@ -0,0 +1,139 @@
+use crate::{get_time_us, process_exited, release_resources, setup_initial_stack, BlockReason, ExitReason, ProcessState, Scheduler, SignalState, SleepEntry, PROCS, SCHEDULER, SLEEP_QUEUE};
+use core::ptr;
+
+/*
+ * Erlang-style restart policy, decided when a process exits or faults
+ * */
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+    Never,      // Leave the process as a zombie
+    OnFailure,  // Restart after a fault or a non-zero exit code
+    Always,     // Restart whatever the exit reason
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RestartSpec {
+    pub policy: RestartPolicy,
+    pub max_restarts: u8,   // Restarts allowed inside one window
+    pub window_ms: u32,     // Length of the restart window
+    pub backoff_ms: u32,    // Delay before the first restart, doubled for each further one
+}
+
+impl RestartSpec {
+    pub const NEVER: RestartSpec = RestartSpec::new(RestartPolicy::Never, 0, 0, 0);
+
+    pub const fn new(policy: RestartPolicy, max_restarts: u8,
+        window_ms: u32, backoff_ms: u32) -> Self {
+        Self { policy, max_restarts, window_ms, backoff_ms }
+    }
+}
+
+/*
+ * Per process supervisor state, lives in the PCB
+ * */
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Supervisor {
+    pub spec: RestartSpec,
+    pub restarts: u8,       // Restarts inside the current window
+    pub window_start: u64,  // Time (us) the current window opened
+}
+
+// Cap on the backoff doubling, so the delay cannot overflow
+const MAX_BACKOFF_SHIFT: u8 = 10;
+
+impl Supervisor {
+    pub const fn new(spec: RestartSpec) -> Self {
+        Self { spec, restarts: 0, window_start: 0 }
+    }
+
+    /*
+     * Decide whether to restart, and account for it in the window.
+     * Returns the backoff delay in us when the process should restart
+     * */
+    fn should_restart(&mut self, reason: ExitReason, now: u64) -> Option<u64> {
+        let wanted = match self.spec.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => reason != ExitReason::Exited(0),
+            RestartPolicy::Always => true,
+        };
+        if !wanted {
+            return None;
+        }
+
+        let window_us = self.spec.window_ms as u64 * 1000;
+        if now - self.window_start > window_us {
+            self.window_start = now;
+            self.restarts = 0;
+        }
+
+        if self.restarts >= self.spec.max_restarts {
+            return None;
+        }
+
+        let shift = self.restarts.min(MAX_BACKOFF_SHIFT);
+        self.restarts += 1;
+        Some((self.spec.backoff_ms as u64 * 1000) << shift)
+    }
+}
+
+/*
+ * Called once a process is done running, either from exit() or a fault.
+ * The supervisor either rebuilds the process from its original entry and
+ * argument, or leaves it as a zombie.
+ *
+ * Must not be called on the running process before its context has been
+ * saved, get_new_sp takes care of that for the current process.
+ * */
+pub fn terminate(pid: u8, reason: ExitReason) {
+    unsafe {
+        let pcb = match PROCS[pid as usize].as_mut() {
+            Some(pcb) => pcb,
+            None => return,
+        };
+
+        // Whatever it held is dropped, a restart begins from scratch
+        release_resources(pid);
+
+        let now = get_time_us();
+        let delay = match pcb.supervisor.should_restart(reason, now) {
+            Some(delay) => delay,
+            None => {
+                pcb.state = ProcessState::Zombie(reason);
+                process_exited(pid);
+                return;
+            }
+        };
+
+        // The spawned closure above the frame is left in place for the new run
+        let frame_top = pcb.stack_size - pcb.payload_size;
+        pcb.sp = setup_initial_stack(pcb.stack_base, frame_top, pcb.entry, pcb.arg);
+        pcb.heap_used = 0;
+        pcb.deadline = None;
+        pcb.wake_value = None;
+        pcb.signals = SignalState::EMPTY;
+
+        if delay == 0 {
+            pcb.state = ProcessState::Ready;
+            let sched = ptr::addr_of_mut!(SCHEDULER);
+            if (*sched).enqueue(pid).is_err() {
+                pcb.state = ProcessState::Zombie(reason);
+                process_exited(pid);
+            }
+            return;
+        }
+
+        // Back off through the sleep queue, the process wakes up fresh
+        let wake_time = now + delay;
+        let q = ptr::addr_of_mut!(SLEEP_QUEUE);
+        match (*q).enqueue(SleepEntry { pid, wake_time }) {
+            Ok(()) => pcb.state = ProcessState::Blocked(BlockReason::RestartBackoff(wake_time)),
+            Err(_) => {
+                pcb.state = ProcessState::Zombie(reason);
+                process_exited(pid);
+            }
+        }
+    }
+}
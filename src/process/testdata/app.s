@@ -0,0 +1,45 @@
+@ Source of the ELF fixtures used by the tests in elf.rs. Rebuild with
+@ llvm-mc and the rust-lld shipped with rustup:
+@
+@   llvm-mc -triple=thumbv6m-none-eabi -filetype=obj app.s -o app.o
+@   FLAGS="--no-dynamic-linker -z max-page-size=4 -z norelro --hash-style=sysv --strip-all -e _start"
+@   rust-lld -flavor gnu -pie $FLAGS app.o -o pie.elf      # R_ARM_RELATIVE only
+@   rust-lld -flavor gnu -shared $FLAGS app.o -o got.elf   # RELATIVE, ABS32 and GLOB_DAT
+@
+@ bad_magic.elf is pie.elf with "ELG" for "ELF", truncated_phdrs.elf is
+@ its first 120 bytes, cut in the middle of the program headers.
+
+    .syntax unified
+    .cpu cortex-m0plus
+    .thumb
+
+    .text
+    .global _start
+    .type _start, %function
+    .thumb_func
+_start:
+    ldr r0, .Lcounter
+    ldr r1, .Lgot_base
+    b .Lloop
+.Lloop:
+    b .Lloop
+    .p2align 2
+.Lcounter:
+    .word counter(GOT)
+.Lgot_base:
+    .word _GLOBAL_OFFSET_TABLE_ - .
+
+    .data
+    .global table
+    .p2align 2
+table:
+    .word _start
+    .word message
+message:
+    .ascii "hi\0\0"
+
+    .bss
+    .global counter
+    .p2align 2
+counter:
+    .space 16
@@ -0,0 +1,21 @@
+use crate::{new_process, ProcessBuilder, ProcessError};
+use crate::scheduler::IDLE;
+
+const IDLE_STACK_SIZE: usize = 256;
+
+/*
+ * Runs whenever no other process is ready, never enters the run queue
+ * */
+fn idle(_arg: *mut ()) -> ! {
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+pub fn create_idle_process() -> Result<u8, ProcessError> {
+    unsafe {
+        let pid = new_process(&ProcessBuilder::new(idle).name("idle").stack_size(IDLE_STACK_SIZE))?;
+        IDLE = Some(pid);
+        Ok(pid)
+    }
+}
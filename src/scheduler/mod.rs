@@ -1,17 +1,23 @@
 pub mod scheduler;
 pub mod round_robin;
 pub mod sleep;
+pub mod idle;
 
 pub use scheduler::*;
 pub use round_robin::*;
 pub use sleep::*;
+pub use idle::*;
 
 use crate::PCB;
 
 
-const MAX_PROCS: usize = 256;
+// Bounded by the 20K kernel RAM region, every PCB slot lives there
+pub(crate) const MAX_PROCS: usize = 32;
 
 pub static mut SCHEDULER: RR = RR::new();
 pub static mut PROCS: [Option<PCB>; MAX_PROCS] = [None; MAX_PROCS];
 pub static mut CURRENT: Option<u8> = None; 
+pub static mut IDLE: Option<u8> = None;
 pub static mut SLEEP_QUEUE: SleepQueue = SleepQueue::new();
+// Process to run at the next switch instead of the head of the run queue
+pub static mut HANDOFF: Option<u8> = None;
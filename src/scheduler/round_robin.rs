@@ -17,6 +17,18 @@ impl RR {
             size: 0, 
         }
     }
+
+    /*
+     * Drop pid from the queue, keeping the order of the others
+     * */
+    pub fn remove(&mut self, pid: u8) {
+        let size = self.size;
+        for _ in 0..size {
+            if let Some(entry) = self.dequeue().ok().filter(|&entry| entry != pid) {
+                let _ = self.enqueue(entry);
+            }
+        }
+    }
 }
 
 impl Scheduler<u8> for RR {
@@ -1,13 +1,16 @@
 use core::ptr;
-use crate::{scheduler::{CURRENT, PROCS, SCHEDULER}, PCB};
+use crate::{scheduler::{CURRENT, HANDOFF, MAX_PROCS, PROCS, SCHEDULER, SLEEP_QUEUE}, get_time_us, BlockReason, ProcessState, SleepEntry, PCB};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum SchedulerError {
     NoSpace, 
     Empty, 
     NoCurrent, 
     ProcessNotFound,
     NotRunnable, 
+    PermissionDenied,
+    TimedOut,
+    Interrupted,    // Woken early by wake()
 }
 
 pub trait Scheduler<T> {
@@ -19,13 +22,113 @@ pub fn current() -> Option<u8> {
     unsafe { CURRENT }
 }
 
+/*
+ * PCB of a live process, None for a free slot or a pid out of range.
+ * Callers keep interrupts off while they hold on to it.
+ * */
+pub fn get_pcb(pid: u8) -> Option<&'static mut PCB> {
+    if pid as usize >= MAX_PROCS {
+        return None;
+    }
+    unsafe { PROCS[pid as usize].as_mut() }
+}
+
 /// Voluntary yield - triggers PendSV to do the actual context switch
 /// This ensures we always switch in handler mode with proper exception frame
 pub fn yield_now() -> Result<(), SchedulerError> {
     // Trigger PendSV - the PendSV handler will do the actual switch
+    // Host tests have nothing to switch to
+    #[cfg(target_arch = "arm")]
     cortex_m::peripheral::SCB::set_pendsv();
     // PendSV is lowest priority, will run when we exit this function
     Ok(())
 }
 
+/*
+ * Yield straight to pid, skipping the run queue. Used where the next
+ * process to run is known, like an IPC call waking its server.
+ * */
+pub fn switch_to(pid: u8) -> Result<(), SchedulerError> {
+    unsafe { HANDOFF = Some(pid) };
+    yield_now()
+}
+
+/*
+ * Mark the running process as blocked. It leaves the run queue at the next
+ * context switch, callers follow up with yield_now()
+ * */
+pub fn block_current(reason: BlockReason) -> Result<u8, SchedulerError> {
+    block_current_until(reason, None)
+}
+
+/*
+ * Same as block_current, and wake the process up at deadline (us) even if
+ * nobody unblocks it. Blocking calls pass the same deadline on each retry,
+ * the sleep queue only gets one entry for it.
+ * */
+pub fn block_current_until(reason: BlockReason, deadline: Option<u64>) -> Result<u8, SchedulerError> {
+    unsafe {
+        let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+        let pcb = PROCS[pid as usize].as_mut().ok_or(SchedulerError::ProcessNotFound)?;
+        // Any earlier timeout is dropped, its sleep entry no longer matches
+        if let Some(wake_time) = deadline.filter(|_| pcb.deadline != deadline) {
+            let q = ptr::addr_of_mut!(SLEEP_QUEUE);
+            (*q).enqueue(SleepEntry { pid, wake_time })?;
+        }
+        // Only blocked once the timeout is queued, a full queue leaves the caller running
+        pcb.deadline = deadline;
+        pcb.state = ProcessState::Blocked(reason);
+        Ok(pid)
+    }
+}
 
+// Absolute deadline of a blocking call, None waits forever
+pub fn deadline_after(timeout_ms: Option<u32>) -> Option<u64> {
+    timeout_ms.map(|ms| get_time_us() + ms as u64 * 1000)
+}
+
+pub fn deadline_passed(deadline: Option<u64>) -> bool {
+    deadline.is_some_and(|d| get_time_us() >= d)
+}
+
+/*
+ * Value handed to the running process by whoever woke it up, taken once
+ * */
+pub fn take_wake_value() -> Option<u32> {
+    unsafe {
+        let pid = CURRENT?;
+        PROCS[pid as usize].as_mut()?.wake_value.take()
+    }
+}
+
+/*
+ * Make a blocked process runnable again
+ * */
+pub fn unblock(pid: u8) -> Result<(), SchedulerError> {
+    unsafe {
+        let pcb = PROCS[pid as usize].as_mut().ok_or(SchedulerError::ProcessNotFound)?;
+        if !matches!(pcb.state, ProcessState::Blocked(_)) {
+            return Err(SchedulerError::NotRunnable);
+        }
+        pcb.state = ProcessState::Ready;
+
+        // Its timeout, if any, is not needed anymore
+        let q = ptr::addr_of_mut!(SLEEP_QUEUE);
+        (*q).remove(pid);
+        pcb.deadline = None;
+
+        let sched = ptr::addr_of_mut!(SCHEDULER);
+        (*sched).enqueue(pid)
+    }
+}
+
+/*
+ * Unblock pid and leave it value to pick up with take_wake_value()
+ * */
+pub fn unblock_with(pid: u8, value: u32) -> Result<(), SchedulerError> {
+    unblock(pid)?;
+    if let Some(pcb) = unsafe { PROCS[pid as usize].as_mut() } {
+        pcb.wake_value = Some(value);
+    }
+    Ok(())
+}
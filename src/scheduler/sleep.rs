@@ -1,5 +1,5 @@
 use crate::scheduler::{Scheduler, MAX_PROCS};
-use crate::{SchedulerError, PROCS, SCHEDULER, SLEEP_QUEUE};
+use crate::{BlockReason, ProcessState, SchedulerError, PROCS, SCHEDULER, SLEEP_QUEUE};
 use core::ptr; 
 
 /*
@@ -15,6 +15,12 @@ pub unsafe fn register_timer(timer: &rp2040_hal::Timer) {
     }
 }
 
+#[cfg(test)]
+pub fn get_time_us() -> u64 {
+    crate::test_support::now_us()
+}
+
+#[cfg(not(test))]
 pub fn get_time_us() -> u64 {
     unsafe {
         if TIMER.is_null() {
@@ -28,7 +34,7 @@ pub fn get_time_us() -> u64 {
 /*
  * Sleep queue
  * */
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct SleepEntry {
     pub pid: u8, 
     pub wake_time: u64,
@@ -36,9 +42,13 @@ pub struct SleepEntry {
 
 const DUMMY: SleepEntry = SleepEntry { pid: 0, wake_time: core::u64::MAX, };
 
+// Index of a pid with no entry in the heap
+const NOT_QUEUED: u8 = u8::MAX;
+
 pub struct SleepQueue {
     heap: [SleepEntry; MAX_PROCS],
     size: usize, 
+    index: [u8; MAX_PROCS],     // Heap position of each pid's entry, so it can be removed
 }
 
 impl SleepQueue {
@@ -46,9 +56,17 @@ impl SleepQueue {
         Self {
             heap: [DUMMY; MAX_PROCS], 
             size: 0, 
+            index: [NOT_QUEUED; MAX_PROCS],
         }
     }
 
+    // Swap two heap slots, keeping the index in step
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.index[self.heap[a].pid as usize] = a as u8;
+        self.index[self.heap[b].pid as usize] = b as u8;
+    }
+
     fn parent(&self, idx: usize) -> usize {
         (idx - 1) >> 1 
     }
@@ -69,7 +87,7 @@ impl SleepQueue {
             let child: SleepEntry = self.heap[i];
 
             if parent.wake_time > child.wake_time {
-                self.heap.swap(parent_idx, i);
+                self.swap(parent_idx, i);
                 // Move to parent 
                 i = parent_idx; 
             } else {
@@ -101,7 +119,7 @@ impl SleepQueue {
                 break;
             }
             
-            self.heap.swap(i, smallest);
+            self.swap(i, smallest);
             i = smallest;
         }
     }
@@ -114,24 +132,77 @@ impl SleepQueue {
             return Err(SchedulerError::Empty);
         }
         let min_node = self.heap[0];
+        self.take(0);
+        Ok(min_node)
+    }
 
-        // Perform bubble down here
+    /*
+     * Drop the entry at idx, the last entry fills the hole and moves
+     * whichever way it has to
+     * */
+    fn take(&mut self, idx: usize) {
+        self.index[self.heap[idx].pid as usize] = NOT_QUEUED;
         self.size -= 1;
-        self.heap[0] = self.heap[self.size];
-        self.bubble_down(0);
+        if idx < self.size {
+            self.heap[idx] = self.heap[self.size];
+            self.index[self.heap[idx].pid as usize] = idx as u8;
+            self.bubble_up(idx);
+            self.bubble_down(idx);
+        }
+    }
 
-        Ok(min_node)
+    /*
+     * Cancel the pending wake up of pid, false if it had none
+     * */
+    pub fn remove(&mut self, pid: u8) -> bool {
+        match self.index.get(pid as usize) {
+            Some(&idx) if idx != NOT_QUEUED => {
+                self.take(idx as usize);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /*
+     * Earliest entry, if its wake time has passed by now (us)
+     * */
+    pub fn pop_expired_at(&mut self, now: u64) -> Result<SleepEntry, SchedulerError> {
+        if self.size == 0 {
+            return Err(SchedulerError::Empty);
+        }
+
+        if self.heap[0].wake_time > now {
+            return Err(SchedulerError::NotRunnable);
+        }
+
+        self.extract_min()
+    }
+
+    pub fn pop_expired(&mut self) -> Result<SleepEntry, SchedulerError> {
+        self.pop_expired_at(get_time_us())
     }
 
     pub fn get_size(&self) -> usize { self.size }
 }
 
+impl Default for SleepQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Scheduler<SleepEntry> for SleepQueue {
     /*
      * Heap insertion
-     * Assumed entry->wake_time is not defined when this function is called 
+     * A pid has one entry at most, a new wake time replaces the old one,
+     * which could only have been stale
      * */
     fn enqueue(&mut self, node: SleepEntry) -> Result<(), SchedulerError> {
+        if node.pid as usize >= MAX_PROCS {
+            return Err(SchedulerError::ProcessNotFound);
+        }
+        self.remove(node.pid);
         if self.size == MAX_PROCS {
             return Err(SchedulerError::NoSpace);
         }
@@ -139,26 +210,17 @@ impl Scheduler<SleepEntry> for SleepQueue {
         // Heap insertion, size is equivalent to last element 
         let last_idx = self.size; 
         self.heap[last_idx] = node;
+        self.index[node.pid as usize] = last_idx as u8;
+        self.size += 1; 
 
         // Bubble up 
         self.bubble_up(last_idx);
-
-        self.size += 1; 
         Ok(())
     }
     
 
     fn dequeue(&mut self) -> Result<u8, SchedulerError> {
-        if self.size == 0 {
-            return Err(SchedulerError::Empty);
-        }
-
-        let now = get_time_us(); 
-        if self.heap[0].wake_time > now {
-            return Err(SchedulerError::NotRunnable);
-        }
-
-        Ok(self.extract_min()?.pid)
+        Ok(self.pop_expired()?.pid)
     }
 }
 
@@ -168,14 +230,22 @@ impl Scheduler<SleepEntry> for SleepQueue {
 pub fn check_sleep_and_wake() -> Result<u8, SchedulerError> {
     unsafe {
         let q = core::ptr::addr_of_mut!(SLEEP_QUEUE);
-        match (*q).dequeue() {
-            Ok(pid) => {
+        match (*q).pop_expired() {
+            Ok(SleepEntry { pid, wake_time }) => {
                 let idx = pid as usize;
 
                 let proc = PROCS[idx]
                     .as_mut()
                     .ok_or(SchedulerError::ProcessNotFound)?;
 
+                match proc.state {
+                    ProcessState::Blocked(BlockReason::Sleeping(t) | BlockReason::RestartBackoff(t)) if t == wake_time => {}
+                    // Blocking call with a timeout, it sees the deadline passed and gives up
+                    ProcessState::Blocked(_) if proc.deadline == Some(wake_time) => proc.deadline = None,
+                    // Restarted or reused pid, kill and unblock take entries out but stay safe
+                    _ => return Ok(idx as u8),
+                }
+
                 proc.state = crate::ProcessState::Ready; 
 
                 let sched = ptr::addr_of_mut!(SCHEDULER); 
@@ -187,3 +257,139 @@ pub fn check_sleep_and_wake() -> Result<u8, SchedulerError> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn entry(pid: u8, wake_time: u64) -> SleepEntry {
+        SleepEntry { pid, wake_time }
+    }
+
+    fn queue(wakes: &[(u8, u64)]) -> SleepQueue {
+        let mut q = SleepQueue::new();
+        for &(pid, wake_time) in wakes {
+            q.enqueue(entry(pid, wake_time)).unwrap();
+        }
+        q
+    }
+
+    // Every queued pid points at its heap slot, every other pid at nothing
+    fn check_index(q: &SleepQueue) {
+        for pid in 0..MAX_PROCS {
+            match q.heap[..q.size].iter().position(|e| e.pid as usize == pid) {
+                Some(i) => assert_eq!(q.index[pid] as usize, i, "pid {}", pid),
+                None => assert_eq!(q.index[pid], NOT_QUEUED, "pid {}", pid),
+            }
+        }
+    }
+
+    fn drain(q: &mut SleepQueue) -> Vec<(u8, u64)> {
+        let mut out = Vec::new();
+        while let Ok(e) = q.pop_expired_at(u64::MAX) {
+            out.push((e.pid, e.wake_time));
+        }
+        out
+    }
+
+    const WAKES: [(u8, u64); 7] = [(3, 70), (1, 10), (4, 40), (2, 20), (6, 60), (5, 50), (0, 30)];
+
+    #[test]
+    fn pops_in_wake_order() {
+        let mut q = queue(&WAKES);
+        check_index(&q);
+        assert_eq!(q.pop_expired_at(9).unwrap_err(), SchedulerError::NotRunnable);
+        assert_eq!(drain(&mut q), [(1, 10), (2, 20), (0, 30), (4, 40), (5, 50), (6, 60), (3, 70)]);
+        assert_eq!(q.pop_expired_at(u64::MAX).unwrap_err(), SchedulerError::Empty);
+        check_index(&q);
+    }
+
+    #[test]
+    fn remove_root() {
+        let mut q = queue(&WAKES);
+        assert_eq!(q.heap[0].pid, 1);
+        assert!(q.remove(1));
+        check_index(&q);
+        assert_eq!(drain(&mut q), [(2, 20), (0, 30), (4, 40), (5, 50), (6, 60), (3, 70)]);
+    }
+
+    #[test]
+    fn remove_middle() {
+        let mut q = queue(&WAKES);
+        let pid = q.heap[2].pid;
+        assert!(q.remove(pid));
+        check_index(&q);
+        let expected: Vec<_> = [(1, 10), (2, 20), (0, 30), (4, 40), (5, 50), (6, 60), (3, 70)]
+            .into_iter()
+            .filter(|&(p, _)| p != pid)
+            .collect();
+        assert_eq!(drain(&mut q), expected);
+    }
+
+    #[test]
+    fn remove_last() {
+        let mut q = queue(&WAKES);
+        let pid = q.heap[q.size - 1].pid;
+        assert!(q.remove(pid));
+        assert_eq!(q.get_size(), WAKES.len() - 1);
+        check_index(&q);
+        assert!(!q.remove(pid));
+        assert!(!drain(&mut q).iter().any(|&(p, _)| p == pid));
+    }
+
+    #[test]
+    fn remove_unknown_pid() {
+        let mut q = queue(&WAKES);
+        assert!(!q.remove(7));
+        assert!(!q.remove(MAX_PROCS as u8));
+        assert_eq!(q.get_size(), WAKES.len());
+    }
+
+    #[test]
+    fn enqueue_replaces_queued_pid() {
+        let mut q = queue(&WAKES);
+        // Later, then earlier than everything
+        q.enqueue(entry(1, 65)).unwrap();
+        check_index(&q);
+        q.enqueue(entry(3, 5)).unwrap();
+        check_index(&q);
+        assert_eq!(q.get_size(), WAKES.len());
+        assert_eq!(drain(&mut q), [(3, 5), (2, 20), (0, 30), (4, 40), (5, 50), (6, 60), (1, 65)]);
+    }
+
+    #[test]
+    fn index_follows_mixed_operations() {
+        let mut q = SleepQueue::new();
+        let mut x: u32 = 0x2545_F491;
+        for _ in 0..2000 {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            let pid = (x % MAX_PROCS as u32) as u8;
+            match x >> 28 {
+                0..=9 => q.enqueue(entry(pid, (x >> 8) as u64 % 1000)).unwrap(),
+                10..=13 => { q.remove(pid); }
+                _ => { let _ = q.pop_expired_at((x >> 8) as u64 % 1000); }
+            }
+            check_index(&q);
+        }
+
+        let popped = drain(&mut q);
+        assert!(popped.windows(2).all(|w| w[0].1 <= w[1].1));
+        check_index(&q);
+    }
+
+    #[test]
+    fn full_queue() {
+        let mut q = SleepQueue::new();
+        for pid in 0..MAX_PROCS as u8 {
+            q.enqueue(entry(pid, 100 - pid as u64)).unwrap();
+        }
+        // A queued pid still gets its new wake time
+        q.enqueue(entry(0, 1)).unwrap();
+        check_index(&q);
+        assert_eq!(q.enqueue(entry(MAX_PROCS as u8, 1)).unwrap_err(), SchedulerError::ProcessNotFound);
+        assert_eq!(q.pop_expired_at(u64::MAX).unwrap().pid, 0);
+    }
+}
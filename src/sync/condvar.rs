@@ -0,0 +1,167 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current_until, deadline_after, deadline_passed, get_mutex, hand_off, mutex_lock, unblock, yield_now,
+    BlockReason, LockError, ProcessState, SchedulerError, CURRENT, PROCS};
+
+pub const MAX_CONDVARS: usize = 8;
+
+/*
+ * Condition variable used with a kernel mutex. A signalled waiter is
+ * moved onto the mutex queue rather than woken, it runs again once the
+ * mutex is handed to it, in turn with the other lockers.
+ * */
+#[derive(Clone, Copy)]
+pub struct CondVar {
+    next_ticket: u32,
+    handles: u8,
+}
+
+impl CondVar {
+    pub const fn new() -> Self {
+        Self { next_ticket: 0, handles: 0 }
+    }
+}
+
+impl Default for CondVar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static mut CONDVARS: [Option<CondVar>; MAX_CONDVARS] = [None; MAX_CONDVARS];
+
+fn get_condvar(id: u8) -> Result<&'static mut CondVar, LockError> {
+    if id as usize >= MAX_CONDVARS {
+        return Err(LockError::NoCondVar);
+    }
+    unsafe { CONDVARS[id as usize].as_mut().ok_or(LockError::NoCondVar) }
+}
+
+pub(crate) fn condvar_create() -> Result<u8, LockError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let id = (0..MAX_CONDVARS)
+            .find(|&i| CONDVARS[i].is_none())
+            .ok_or(LockError::TooManyCondVars)?;
+        CONDVARS[id] = Some(CondVar::new());
+        Ok(id as u8)
+    })
+}
+
+/*
+ * Handles are counted like on mutexes, the last one takes it away
+ * */
+pub(crate) fn condvar_retain(id: u8) {
+    if let Ok(cv) = get_condvar(id) {
+        cv.handles += 1;
+    }
+}
+
+pub(crate) fn condvar_release(id: u8) {
+    if let Ok(cv) = get_condvar(id) {
+        cv.handles = cv.handles.saturating_sub(1);
+        if cv.handles == 0 {
+            unsafe { CONDVARS[id as usize] = None };
+        }
+    }
+}
+
+/*
+ * Release mutex and wait for a signal, atomically. The mutex is held
+ * again on return, timeout included.
+ * */
+pub(crate) fn cond_wait(cv: u8, mutex: u8, timeout_ms: Option<u32>) -> Result<(), LockError> {
+    let deadline = deadline_after(timeout_ms);
+    let ticket = cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+        let m = get_mutex(mutex)?;
+        if m.owner != Some(pid) {
+            return Err(LockError::NotOwner);
+        }
+        let c = get_condvar(cv)?;
+        let ticket = c.next_ticket;
+        c.next_ticket = c.next_ticket.wrapping_add(1);
+
+        block_current_until(BlockReason::WaitingForCondVar { cv, mutex, ticket }, deadline)?;
+        hand_off(mutex, m);
+        Ok(ticket)
+    })?;
+    yield_now()?;
+
+    loop {
+        let signalled = cortex_m::interrupt::free(|_| unsafe {
+            let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+            // signal() queued us on the mutex and unlock handed it over
+            if get_mutex(mutex)?.owner == Some(pid) {
+                return Ok(Some(true));
+            }
+            if deadline_passed(deadline) {
+                return Ok(Some(false));
+            }
+            block_current_until(BlockReason::WaitingForCondVar { cv, mutex, ticket }, deadline)?;
+            Ok::<_, LockError>(None)
+        })?;
+
+        match signalled {
+            Some(true) => return Ok(()),
+            Some(false) => {
+                mutex_lock(mutex)?;
+                return Err(LockError::TimedOut);
+            }
+            None => yield_now()?,
+        }
+    }
+}
+
+// Waiter on cv holding the lowest ticket, with the mutex it waits with
+fn next_waiter(id: u8) -> Option<(u8, u8)> {
+    unsafe {
+        (0..MAX_PROCS as u8)
+            .filter_map(|pid| match PROCS[pid as usize].as_ref().map(|pcb| pcb.state) {
+                Some(ProcessState::Blocked(BlockReason::WaitingForCondVar { cv, mutex, ticket })) if cv == id =>
+                    Some((ticket, pid, mutex)),
+                _ => None,
+            })
+            .min()
+            .map(|(_, pid, mutex)| (pid, mutex))
+    }
+}
+
+/*
+ * Move one waiter from the condition variable to its mutex, returns
+ * false if nobody was waiting
+ * */
+fn wake_one(id: u8) -> Result<bool, LockError> {
+    let (pid, mutex) = match next_waiter(id) {
+        Some(waiter) => waiter,
+        None => return Ok(false),
+    };
+    let m = get_mutex(mutex)?;
+    unsafe {
+        let pcb = PROCS[pid as usize].as_mut().ok_or(SchedulerError::ProcessNotFound)?;
+        // Signalled in time, the timeout no longer applies
+        pcb.deadline = None;
+        if m.owner.is_none() {
+            m.owner = Some(pid);
+            unblock(pid)?;
+        } else {
+            let ticket = m.take_ticket();
+            pcb.state = ProcessState::Blocked(BlockReason::WaitingForMutex { mutex, ticket });
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) fn cond_signal(cv: u8) -> Result<(), LockError> {
+    cortex_m::interrupt::free(|_| {
+        get_condvar(cv)?;
+        wake_one(cv)?;
+        Ok(())
+    })
+}
+
+pub(crate) fn cond_broadcast(cv: u8) -> Result<(), LockError> {
+    cortex_m::interrupt::free(|_| {
+        get_condvar(cv)?;
+        while wake_one(cv)? {}
+        Ok(())
+    })
+}
@@ -0,0 +1,187 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current_until, deadline_after, deadline_passed, take_wake_value, unblock_with, wake_pollers,
+    yield_now, BlockReason, KernelObject, ProcessState, SchedulerError, CURRENT, PROCS};
+
+pub const MAX_EVENT_GROUPS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitMode {
+    All,    // Every bit of the mask
+    Any,    // At least one bit of the mask
+}
+
+#[derive(Debug)]
+pub enum EventError {
+    NoGroup,
+    TooManyGroups,
+    InvalidMask,
+    TimedOut,
+    Scheduler(SchedulerError),
+}
+
+impl From<SchedulerError> for EventError {
+    fn from(e: SchedulerError) -> Self {
+        EventError::Scheduler(e)
+    }
+}
+
+/*
+ * 32 event flags. Waiters park on a mask and are checked every time
+ * flags get set.
+ * */
+#[derive(Clone, Copy)]
+pub struct EventGroup {
+    bits: u32,
+    handles: u8,
+}
+
+impl EventGroup {
+    pub const fn new() -> Self {
+        Self { bits: 0, handles: 0 }
+    }
+
+    pub fn bits(&self) -> u32 { self.bits }
+}
+
+impl Default for EventGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn satisfied(bits: u32, mask: u32, all: bool) -> bool {
+    if all { bits & mask == mask } else { bits & mask != 0 }
+}
+
+pub static mut EVENT_GROUPS: [Option<EventGroup>; MAX_EVENT_GROUPS] = [None; MAX_EVENT_GROUPS];
+
+fn get_group(id: u8) -> Result<&'static mut EventGroup, EventError> {
+    if id as usize >= MAX_EVENT_GROUPS {
+        return Err(EventError::NoGroup);
+    }
+    unsafe { EVENT_GROUPS[id as usize].as_mut().ok_or(EventError::NoGroup) }
+}
+
+pub(crate) fn event_group_create() -> Result<u8, EventError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let id = (0..MAX_EVENT_GROUPS)
+            .find(|&i| EVENT_GROUPS[i].is_none())
+            .ok_or(EventError::TooManyGroups)?;
+        EVENT_GROUPS[id] = Some(EventGroup::new());
+        Ok(id as u8)
+    })
+}
+
+/*
+ * The group goes away with the last handle on it, waiters hold one
+ * */
+pub(crate) fn event_group_retain(id: u8) {
+    if let Ok(group) = get_group(id) {
+        group.handles += 1;
+    }
+}
+
+pub(crate) fn event_group_release(id: u8) {
+    if let Ok(group) = get_group(id) {
+        group.handles = group.handles.saturating_sub(1);
+        if group.handles == 0 {
+            unsafe { EVENT_GROUPS[id as usize] = None };
+        }
+    }
+}
+
+pub(crate) fn event_get(id: u8) -> Result<u32, EventError> {
+    cortex_m::interrupt::free(|_| Ok(get_group(id)?.bits))
+}
+
+/*
+ * Set flags and release every waiter they satisfy. Each one gets the
+ * flags as they were when it was released, flags asked to be cleared on
+ * exit are cleared once all waiters have been looked at.
+ * Safe to call from interrupts. Returns the flags left set.
+ * */
+pub(crate) fn event_set(id: u8, bits: u32) -> Result<u32, EventError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let group = get_group(id)?;
+        group.bits |= bits;
+
+        let mut to_clear = 0;
+        for pid in 0..MAX_PROCS as u8 {
+            let (mask, all, clear) = match PROCS[pid as usize].as_ref().map(|pcb| pcb.state) {
+                Some(ProcessState::Blocked(BlockReason::WaitingForEvents { group, mask, all, clear }))
+                    if group == id => (mask, all, clear),
+                _ => continue,
+            };
+            if satisfied(group.bits, mask, all) {
+                let _ = unblock_with(pid, group.bits);
+                if clear {
+                    to_clear |= mask;
+                }
+            }
+        }
+
+        group.bits &= !to_clear;
+        if group.bits != 0 {
+            wake_pollers(KernelObject::EventGroup(id));
+        }
+        Ok(group.bits)
+    })
+}
+
+/*
+ * Clear flags, returns the flags before clearing
+ * */
+pub(crate) fn event_clear(id: u8, bits: u32) -> Result<u32, EventError> {
+    cortex_m::interrupt::free(|_| {
+        let group = get_group(id)?;
+        let old = group.bits;
+        group.bits &= !bits;
+        Ok(old)
+    })
+}
+
+/*
+ * Block until all or any of the flags in mask are set, or timeout_ms
+ * passes. With clear_on_exit the mask flags are cleared on the way out.
+ * Returns the flags that satisfied the wait.
+ * */
+pub(crate) fn event_wait(id: u8, mask: u32, mode: WaitMode, clear_on_exit: bool,
+    timeout_ms: Option<u32>) -> Result<u32, EventError> {
+    if mask == 0 {
+        return Err(EventError::InvalidMask);
+    }
+    let all = mode == WaitMode::All;
+    let deadline = deadline_after(timeout_ms);
+    let reason = BlockReason::WaitingForEvents { group: id, mask, all, clear: clear_on_exit };
+
+    loop {
+        let done = cortex_m::interrupt::free(|_| unsafe {
+            CURRENT.ok_or(SchedulerError::NoCurrent)?;
+
+            // event_set already checked us and cleared on our behalf
+            if let Some(bits) = take_wake_value() {
+                return Ok(Some(bits));
+            }
+
+            let group = get_group(id)?;
+            if satisfied(group.bits, mask, all) {
+                let bits = group.bits;
+                if clear_on_exit {
+                    group.bits &= !mask;
+                }
+                return Ok(Some(bits));
+            }
+
+            if deadline_passed(deadline) {
+                return Err(EventError::TimedOut);
+            }
+            block_current_until(reason, deadline)?;
+            Ok(None)
+        })?;
+
+        if let Some(bits) = done {
+            return Ok(bits);
+        }
+        yield_now()?;
+    }
+}
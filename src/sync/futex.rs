@@ -0,0 +1,177 @@
+use crate::{block_current_until, deadline_after, deadline_passed, take_wake_value, unblock_with, user_accessible,
+    yield_now, BlockReason, ProcessState, SchedulerError, CURRENT, PROCS};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+const FUTEX_BUCKETS: usize = 16;
+
+#[derive(Debug)]
+pub enum FutexError {
+    BadAddress,     // Unaligned, or outside the caller's memory
+    WouldBlock,     // Value had already changed, nothing to wait for
+    TimedOut,
+    Scheduler(SchedulerError),
+}
+
+impl From<SchedulerError> for FutexError {
+    fn from(e: SchedulerError) -> Self {
+        FutexError::Scheduler(e)
+    }
+}
+
+/*
+ * FIFO of waiters hashed to this bucket, linked through PCB::futex_next
+ * */
+#[derive(Clone, Copy)]
+struct FutexBucket {
+    head: Option<u8>,
+    tail: Option<u8>,
+}
+
+static mut FUTEX_QUEUES: [FutexBucket; FUTEX_BUCKETS] = [FutexBucket { head: None, tail: None }; FUTEX_BUCKETS];
+
+// Fibonacci hashing, words next to each other land in different buckets
+fn bucket_of(addr: usize) -> usize {
+    ((addr as u32 >> 2).wrapping_mul(0x9E37_79B1) >> 28) as usize
+}
+
+fn next_of(pid: u8) -> Option<u8> {
+    unsafe { PROCS[pid as usize].as_ref().and_then(|pcb| pcb.futex_next) }
+}
+
+fn set_next(pid: u8, next: Option<u8>) {
+    if let Some(pcb) = unsafe { PROCS[pid as usize].as_mut() } {
+        pcb.futex_next = next;
+    }
+}
+
+fn push(bucket: usize, pid: u8) {
+    let q = unsafe { &mut *core::ptr::addr_of_mut!(FUTEX_QUEUES[bucket]) };
+    set_next(pid, None);
+    match q.tail {
+        Some(tail) => set_next(tail, Some(pid)),
+        None => q.head = Some(pid),
+    }
+    q.tail = Some(pid);
+}
+
+/*
+ * Unlink pid from bucket, false if it was not queued there
+ * */
+fn unlink(bucket: usize, pid: u8) -> bool {
+    let q = unsafe { &mut *core::ptr::addr_of_mut!(FUTEX_QUEUES[bucket]) };
+    let mut prev = None;
+    let mut cur = q.head;
+    while let Some(p) = cur {
+        let next = next_of(p);
+        if p == pid {
+            match prev {
+                Some(prev) => set_next(prev, next),
+                None => q.head = next,
+            }
+            if q.tail == Some(pid) {
+                q.tail = prev;
+            }
+            set_next(pid, None);
+            return true;
+        }
+        prev = cur;
+        cur = next;
+    }
+    false
+}
+
+fn check_address(addr: usize) -> Result<(), FutexError> {
+    if !addr.is_multiple_of(4) {
+        return Err(FutexError::BadAddress);
+    }
+    // The kernel may wake anything, processes only their own memory
+    match unsafe { CURRENT } {
+        Some(pid) if !user_accessible(pid, addr, 4, false) => Err(FutexError::BadAddress),
+        _ => Ok(()),
+    }
+}
+
+/*
+ * Block while *futex == expected, until futex_wake on the same address
+ * or timeout_ms. The value is compared with interrupts off, so a wake
+ * that follows a change of the value cannot be missed.
+ * */
+pub fn futex_wait(futex: &AtomicU32, expected: u32, timeout_ms: Option<u32>) -> Result<(), FutexError> {
+    let addr = futex as *const AtomicU32 as usize;
+    check_address(addr)?;
+    let deadline = deadline_after(timeout_ms);
+    let bucket = bucket_of(addr);
+    let mut queued = false;
+
+    loop {
+        let woken = cortex_m::interrupt::free(|_| unsafe {
+            let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+
+            // futex_wake took us off the queue already
+            if take_wake_value().is_some() {
+                return Ok(true);
+            }
+            if !queued {
+                if futex.load(Ordering::Relaxed) != expected {
+                    return Err(FutexError::WouldBlock);
+                }
+                push(bucket, pid);
+                queued = true;
+            }
+            if deadline_passed(deadline) {
+                unlink(bucket, pid);
+                return Err(FutexError::TimedOut);
+            }
+
+            block_current_until(BlockReason::WaitingForFutex(addr), deadline)?;
+            Ok(false)
+        })?;
+
+        if woken {
+            return Ok(());
+        }
+        yield_now()?;
+    }
+}
+
+/*
+ * Wake up to n processes waiting on futex, oldest first. Returns how many
+ * were woken.
+ * */
+pub fn futex_wake(futex: &AtomicU32, n: usize) -> Result<usize, FutexError> {
+    let addr = futex as *const AtomicU32 as usize;
+    check_address(addr)?;
+    let bucket = bucket_of(addr);
+
+    cortex_m::interrupt::free(|_| unsafe {
+        let mut woken = 0;
+        let mut cur = FUTEX_QUEUES[bucket].head;
+        while let Some(pid) = cur {
+            if woken == n {
+                break;
+            }
+            cur = next_of(pid);
+
+            // Same bucket, other address, or already on its way out after a timeout
+            let waiting = matches!(PROCS[pid as usize].as_ref().map(|pcb| pcb.state),
+                Some(ProcessState::Blocked(BlockReason::WaitingForFutex(a))) if a == addr);
+            if waiting {
+                unlink(bucket, pid);
+                unblock_with(pid, 0)?;
+                woken += 1;
+            }
+        }
+        Ok(woken)
+    })
+}
+
+/*
+ * Take a process that stopped running off whatever queue it is on
+ * */
+pub(crate) fn release_futex(pid: u8) {
+    for bucket in 0..FUTEX_BUCKETS {
+        if unlink(bucket, pid) {
+            return;
+        }
+    }
+}
@@ -0,0 +1,9 @@
+pub mod event;
+pub mod mutex;
+pub mod condvar;
+pub mod futex;
+
+pub use event::*;
+pub use mutex::*;
+pub use condvar::*;
+pub use futex::*;
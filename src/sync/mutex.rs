@@ -0,0 +1,186 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current, unblock, yield_now, BlockReason, ProcessState, SchedulerError, CURRENT, PROCS};
+
+pub const MAX_MUTEXES: usize = 8;
+
+#[derive(Debug)]
+pub enum LockError {
+    NoMutex,
+    TooManyMutexes,
+    NoCondVar,
+    TooManyCondVars,
+    NotOwner,
+    WouldDeadlock,      // Locking a mutex we already hold
+    Busy,               // try_lock on a held mutex
+    TimedOut,
+    Scheduler(SchedulerError),
+}
+
+impl From<SchedulerError> for LockError {
+    fn from(e: SchedulerError) -> Self {
+        LockError::Scheduler(e)
+    }
+}
+
+/*
+ * Sleeping mutex. Waiters take a ticket and unlock hands the mutex
+ * straight to the lowest one, so it is granted in FIFO order and a
+ * process that just unlocked cannot barge back in.
+ * */
+#[derive(Clone, Copy)]
+pub struct Mutex {
+    pub(crate) owner: Option<u8>,
+    pub(crate) next_ticket: u32,
+    handles: u8,
+}
+
+impl Mutex {
+    pub const fn new() -> Self {
+        Self { owner: None, next_ticket: 0, handles: 0 }
+    }
+
+    pub fn owner(&self) -> Option<u8> { self.owner }
+
+    pub(crate) fn take_ticket(&mut self) -> u32 {
+        let ticket = self.next_ticket;
+        self.next_ticket = self.next_ticket.wrapping_add(1);
+        ticket
+    }
+}
+
+impl Default for Mutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static mut MUTEXES: [Option<Mutex>; MAX_MUTEXES] = [None; MAX_MUTEXES];
+
+pub(crate) fn get_mutex(id: u8) -> Result<&'static mut Mutex, LockError> {
+    if id as usize >= MAX_MUTEXES {
+        return Err(LockError::NoMutex);
+    }
+    unsafe { MUTEXES[id as usize].as_mut().ok_or(LockError::NoMutex) }
+}
+
+// Waiter on mutex id holding the lowest ticket
+fn next_waiter(id: u8) -> Option<u8> {
+    unsafe {
+        (0..MAX_PROCS as u8)
+            .filter_map(|pid| match PROCS[pid as usize].as_ref().map(|pcb| pcb.state) {
+                Some(ProcessState::Blocked(BlockReason::WaitingForMutex { mutex, ticket })) if mutex == id =>
+                    Some((ticket, pid)),
+                _ => None,
+            })
+            .min()
+            .map(|(_, pid)| pid)
+    }
+}
+
+/*
+ * Pass the mutex to the next waiter, or leave it free
+ * */
+pub(crate) fn hand_off(id: u8, mutex: &mut Mutex) {
+    mutex.owner = next_waiter(id);
+    if let Some(pid) = mutex.owner {
+        let _ = unblock(pid);
+    }
+}
+
+pub(crate) fn mutex_create() -> Result<u8, LockError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let id = (0..MAX_MUTEXES)
+            .find(|&i| MUTEXES[i].is_none())
+            .ok_or(LockError::TooManyMutexes)?;
+        MUTEXES[id] = Some(Mutex::new());
+        Ok(id as u8)
+    })
+}
+
+pub(crate) fn mutex_try_lock(id: u8) -> Result<(), LockError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+        let mutex = get_mutex(id)?;
+        match mutex.owner {
+            None => {
+                mutex.owner = Some(pid);
+                Ok(())
+            }
+            Some(owner) if owner == pid => Err(LockError::WouldDeadlock),
+            Some(_) => Err(LockError::Busy),
+        }
+    })
+}
+
+pub(crate) fn mutex_lock(id: u8) -> Result<(), LockError> {
+    let mut ticket = None;
+    loop {
+        let locked = cortex_m::interrupt::free(|_| unsafe {
+            let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+            let mutex = get_mutex(id)?;
+            match mutex.owner {
+                // Handed over by unlock while we slept
+                Some(owner) if owner == pid && ticket.is_some() => return Ok(true),
+                Some(owner) if owner == pid => return Err(LockError::WouldDeadlock),
+                None => {
+                    mutex.owner = Some(pid);
+                    return Ok(true);
+                }
+                Some(_) => {}
+            }
+
+            // Keep our place in line if we were woken for nothing
+            let t = *ticket.get_or_insert_with(|| mutex.take_ticket());
+            block_current(BlockReason::WaitingForMutex { mutex: id, ticket: t })?;
+            Ok(false)
+        })?;
+
+        if locked {
+            return Ok(());
+        }
+        yield_now()?;
+    }
+}
+
+pub(crate) fn mutex_unlock(id: u8) -> Result<(), LockError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+        let mutex = get_mutex(id)?;
+        if mutex.owner != Some(pid) {
+            return Err(LockError::NotOwner);
+        }
+        hand_off(id, mutex);
+        Ok(())
+    })
+}
+
+/*
+ * Handles on a mutex are counted, it goes away with the last one. Waiters
+ * hold a handle themselves, so nobody is left blocked on it.
+ * */
+pub(crate) fn mutex_retain(id: u8) {
+    if let Ok(mutex) = get_mutex(id) {
+        mutex.handles += 1;
+    }
+}
+
+pub(crate) fn mutex_release(id: u8) {
+    if let Ok(mutex) = get_mutex(id) {
+        mutex.handles = mutex.handles.saturating_sub(1);
+        if mutex.handles == 0 {
+            unsafe { MUTEXES[id as usize] = None };
+        }
+    }
+}
+
+/*
+ * A process that stops running gives up the mutexes it holds
+ * */
+pub(crate) fn release_mutexes(pid: u8) {
+    for id in 0..MAX_MUTEXES as u8 {
+        if let Ok(mutex) = get_mutex(id)
+            && mutex.owner == Some(pid) {
+            hand_off(id, mutex);
+        }
+    }
+}
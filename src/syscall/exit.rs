@@ -0,0 +1,24 @@
+use crate::{yield_now, ExitReason, ProcessState, CURRENT, PROCS};
+
+/*
+ * Terminate the calling process with the given code. The context switch
+ * hands it to the supervisor, which may restart it from its entry point
+ * */
+pub fn exit(code: i32) -> ! {
+    exit_with(ExitReason::Exited(code))
+}
+
+pub(crate) fn exit_with(reason: ExitReason) -> ! {
+    cortex_m::interrupt::free(|_| unsafe {
+        if let Some(pcb) = CURRENT.and_then(|pid| PROCS[pid as usize].as_mut()) {
+            pcb.state = ProcessState::Zombie(reason);
+        }
+    });
+
+    let _ = yield_now();
+
+    // Never scheduled again unless restarted, which starts from entry
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
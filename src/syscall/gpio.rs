@@ -0,0 +1,77 @@
+use crate::{has_capability, Capabilities};
+use rp2040_hal::pac;
+
+pub const GPIO_BANK0_PINS: u8 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GpioError {
+    InvalidPin,
+    Reserved,           // Pin is driven by the kernel
+    PermissionDenied,
+}
+
+// Bank 0 pins only the kernel may touch, one bit per pin
+static mut KERNEL_PINS: u32 = 0;
+
+/*
+ * Take pins for the kernel, processes get Reserved back on them
+ * whatever their capabilities
+ * */
+pub fn reserve_gpio(mask: u32) {
+    cortex_m::interrupt::free(|_| unsafe { KERNEL_PINS |= mask });
+}
+
+fn check_pin(pin: u8) -> Result<u32, GpioError> {
+    if pin >= GPIO_BANK0_PINS {
+        return Err(GpioError::InvalidPin);
+    }
+    if !has_capability(Capabilities::GPIO_BANK0) {
+        return Err(GpioError::PermissionDenied);
+    }
+    let mask = 1 << pin;
+    // The kernel itself may drive its own pins
+    if unsafe { KERNEL_PINS } & mask != 0 && unsafe { crate::CURRENT }.is_some() {
+        return Err(GpioError::Reserved);
+    }
+    Ok(mask)
+}
+
+/*
+ * Hand the pin to SIO and make it an output or an input
+ * */
+pub fn gpio_set_output(pin: u8, output: bool) -> Result<(), GpioError> {
+    let mask = check_pin(pin)?;
+    unsafe {
+        let io = &*pac::IO_BANK0::ptr();
+        let pads = &*pac::PADS_BANK0::ptr();
+        let sio = &*pac::SIO::ptr();
+
+        pads.gpio(pin as usize).modify(|_, w| w.ie().set_bit().od().clear_bit());
+        io.gpio(pin as usize).gpio_ctrl().write(|w| w.funcsel().sio());
+        if output {
+            sio.gpio_oe_set().write(|w| w.bits(mask));
+        } else {
+            sio.gpio_oe_clr().write(|w| w.bits(mask));
+        }
+    }
+    Ok(())
+}
+
+pub fn gpio_write(pin: u8, high: bool) -> Result<(), GpioError> {
+    let mask = check_pin(pin)?;
+    unsafe {
+        let sio = &*pac::SIO::ptr();
+        if high {
+            sio.gpio_out_set().write(|w| w.bits(mask));
+        } else {
+            sio.gpio_out_clr().write(|w| w.bits(mask));
+        }
+    }
+    Ok(())
+}
+
+pub fn gpio_read(pin: u8) -> Result<bool, GpioError> {
+    let mask = check_pin(pin)?;
+    let sio = unsafe { &*pac::SIO::ptr() };
+    Ok(sio.gpio_in().read().bits() & mask != 0)
+}
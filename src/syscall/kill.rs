@@ -0,0 +1,44 @@
+use crate::{exit_with, get_pcb, has_capability, Capabilities, terminate, ExitReason, ProcessState, SchedulerError, CURRENT, IDLE, SCHEDULER, SLEEP_QUEUE};
+use core::ptr;
+
+/*
+ * Terminate another process. It goes through the supervisor like any
+ * other exit, so it may come back if its restart policy says so.
+ * */
+pub fn kill(pid: u8) -> Result<(), SchedulerError> {
+    if unsafe { CURRENT } == Some(pid) {
+        exit_with(ExitReason::Killed);
+    }
+    if !has_capability(Capabilities::KILL) {
+        return Err(SchedulerError::PermissionDenied);
+    }
+    kill_with(pid, ExitReason::Killed)
+}
+
+/*
+ * Terminate pid for reason, callers have checked the right to
+ * */
+pub(crate) fn kill_with(pid: u8, reason: ExitReason) -> Result<(), SchedulerError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        if IDLE == Some(pid) {
+            return Err(SchedulerError::NotRunnable);
+        }
+        let pcb = get_pcb(pid).ok_or(SchedulerError::ProcessNotFound)?;
+
+        match pcb.state {
+            ProcessState::Zombie(_) => return Ok(()),
+            ProcessState::Ready | ProcessState::Running => {
+                let sched = ptr::addr_of_mut!(SCHEDULER);
+                (*sched).remove(pid);
+            }
+            // A pending wake up would bring the pid back after it is gone
+            ProcessState::Blocked(_) => {
+                let q = ptr::addr_of_mut!(SLEEP_QUEUE);
+                (*q).remove(pid);
+            }
+        }
+
+        terminate(pid, reason);
+        Ok(())
+    })
+}
@@ -1,3 +1,19 @@
 pub mod sleep; 
+pub mod exit;
+pub mod wait;
+pub mod sbrk;
+pub mod kill;
+pub mod object;
+pub mod gpio;
+pub mod signal;
+pub mod poll;
 
 pub use sleep::*;
+pub use exit::*;
+pub use wait::*;
+pub use sbrk::*;
+pub use kill::*;
+pub use object::*;
+pub use gpio::*;
+pub use signal::*;
+pub use poll::*;
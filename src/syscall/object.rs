@@ -0,0 +1,426 @@
+use crate::{cond_broadcast, cond_signal, cond_wait, condvar_create, event_clear, event_group_create, event_set,
+    event_wait, get_pcb, mutex_create, mutex_lock, mutex_try_lock, mutex_unlock, pipe_create, pipe_read, pipe_write, pool_alloc, pool_alloc_wait, pool_create, pool_free, shm_create, shm_grant, shm_map, shm_release, timer_create,
+    release_object, retain_object, timer_delete, timer_expirations, timer_reset, timer_set_period, timer_start,
+    timer_stop, Handle, HandleError, HandleTable, KernelObject,
+    Rights, ShmMapping, ShmRights, TimerCallback, TimerMode, WaitMode, CURRENT, PIPE_SIZE};
+
+/*
+ * Handle based entry points to kernel objects. Processes only ever see
+ * handles, the kernel checks the rights of each one before use.
+ * */
+
+pub(crate) fn current_table() -> Result<&'static mut HandleTable, HandleError> {
+    let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+    get_pcb(pid).map(|pcb| &mut pcb.handles).ok_or(HandleError::NoCurrent)
+}
+
+fn lookup(handle: Handle, rights: Rights) -> Result<KernelObject, HandleError> {
+    cortex_m::interrupt::free(|_| Ok(current_table()?.check(handle, rights)?.object))
+}
+
+fn pool_of(handle: Handle, rights: Rights) -> Result<u8, HandleError> {
+    match lookup(handle, rights)? {
+        KernelObject::Pool(id) => Ok(id),
+        _ => Err(HandleError::WrongType),
+    }
+}
+
+fn event_group_of(handle: Handle, rights: Rights) -> Result<u8, HandleError> {
+    match lookup(handle, rights)? {
+        KernelObject::EventGroup(id) => Ok(id),
+        _ => Err(HandleError::WrongType),
+    }
+}
+
+fn mutex_of(handle: Handle) -> Result<u8, HandleError> {
+    match lookup(handle, Rights::WRITE)? {
+        KernelObject::Mutex(id) => Ok(id),
+        _ => Err(HandleError::WrongType),
+    }
+}
+
+fn condvar_of(handle: Handle) -> Result<u8, HandleError> {
+    match lookup(handle, Rights::WRITE)? {
+        KernelObject::CondVar(id) => Ok(id),
+        _ => Err(HandleError::WrongType),
+    }
+}
+
+fn timer_of(handle: Handle) -> Result<u8, HandleError> {
+    match lookup(handle, Rights::WRITE)? {
+        KernelObject::Timer(id) => Ok(id),
+        _ => Err(HandleError::WrongType),
+    }
+}
+
+/*
+ * Install a handle to an object in the table of pid, counting it on the
+ * objects that keep track of their handles
+ * */
+pub(crate) fn install_handle(pid: u8, object: KernelObject, rights: Rights) -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pcb = get_pcb(pid).ok_or(HandleError::NoProcess)?;
+        if let KernelObject::SharedMemory(id) = object {
+            shm_grant(id, pid, shm_rights(rights))?;
+        }
+        let handle = pcb.handles.insert(object, rights)?;
+        retain_object(object);
+        Ok(handle)
+    })
+}
+
+fn shm_rights(rights: Rights) -> ShmRights {
+    if rights.contains(Rights::WRITE) { ShmRights::ReadWrite } else { ShmRights::ReadOnly }
+}
+
+pub fn open_pool(block_size: usize, count: usize) -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+        // Only the handle can take it away again, do not create one we cannot hand out
+        if current_table()?.is_full() {
+            return Err(HandleError::TableFull);
+        }
+        let id = pool_create(block_size, count)?;
+        install_handle(pid, KernelObject::Pool(id), Rights::ALL)
+    })
+}
+
+pub fn open_shm(size: usize) -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let table = current_table()?;
+        if table.is_full() {
+            return Err(HandleError::TableFull);
+        }
+        let id = shm_create(size)?;
+        table.insert(KernelObject::SharedMemory(id), Rights::ALL)
+    })
+}
+
+pub fn open_event_group() -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+        if current_table()?.is_full() {
+            return Err(HandleError::TableFull);
+        }
+        let id = event_group_create()?;
+        install_handle(pid, KernelObject::EventGroup(id), Rights::ALL)
+    })
+}
+
+pub fn set_events(handle: Handle, bits: u32) -> Result<u32, HandleError> {
+    Ok(event_set(event_group_of(handle, Rights::WRITE)?, bits)?)
+}
+
+pub fn clear_events(handle: Handle, bits: u32) -> Result<u32, HandleError> {
+    Ok(event_clear(event_group_of(handle, Rights::WRITE)?, bits)?)
+}
+
+pub fn wait_events(handle: Handle, mask: u32, mode: WaitMode, clear_on_exit: bool,
+    timeout_ms: Option<u32>) -> Result<u32, HandleError> {
+    // Clearing on exit changes the group, that takes WRITE too
+    let rights = if clear_on_exit { Rights::READ.union(Rights::WRITE) } else { Rights::READ };
+    Ok(event_wait(event_group_of(handle, rights)?, mask, mode, clear_on_exit, timeout_ms)?)
+}
+
+pub fn open_mutex() -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+        if current_table()?.is_full() {
+            return Err(HandleError::TableFull);
+        }
+        let id = mutex_create()?;
+        install_handle(pid, KernelObject::Mutex(id), Rights::ALL)
+    })
+}
+
+pub fn open_condvar() -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+        if current_table()?.is_full() {
+            return Err(HandleError::TableFull);
+        }
+        let id = condvar_create()?;
+        install_handle(pid, KernelObject::CondVar(id), Rights::ALL)
+    })
+}
+
+pub fn try_lock(handle: Handle) -> Result<(), HandleError> {
+    Ok(mutex_try_lock(mutex_of(handle)?)?)
+}
+
+pub fn lock(handle: Handle) -> Result<(), HandleError> {
+    Ok(mutex_lock(mutex_of(handle)?)?)
+}
+
+pub fn unlock(handle: Handle) -> Result<(), HandleError> {
+    Ok(mutex_unlock(mutex_of(handle)?)?)
+}
+
+pub fn wait_cond(cv: Handle, mutex: Handle, timeout_ms: Option<u32>) -> Result<(), HandleError> {
+    Ok(cond_wait(condvar_of(cv)?, mutex_of(mutex)?, timeout_ms)?)
+}
+
+pub fn signal_cond(cv: Handle) -> Result<(), HandleError> {
+    Ok(cond_signal(condvar_of(cv)?)?)
+}
+
+pub fn broadcast_cond(cv: Handle) -> Result<(), HandleError> {
+    Ok(cond_broadcast(condvar_of(cv)?)?)
+}
+
+pub fn block_alloc(handle: Handle) -> Result<*mut u8, HandleError> {
+    Ok(pool_alloc(pool_of(handle, Rights::WRITE)?)?)
+}
+
+pub fn block_alloc_wait(handle: Handle) -> Result<*mut u8, HandleError> {
+    Ok(pool_alloc_wait(pool_of(handle, Rights::WRITE)?)?)
+}
+
+pub fn block_free(handle: Handle, block: *mut u8) -> Result<(), HandleError> {
+    Ok(pool_free(pool_of(handle, Rights::WRITE)?, block)?)
+}
+
+/*
+ * New pipe of PIPE_SIZE bytes, returns its (read, write) handles
+ * */
+pub fn open_pipe() -> Result<(Handle, Handle), HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+        if current_table()?.free_slots() < 2 {
+            return Err(HandleError::TableFull);
+        }
+        let id = pipe_create(PIPE_SIZE)?;
+        let read = install_handle(pid, KernelObject::PipeReader(id), Rights::READ.union(Rights::TRANSFER))?;
+        let write = install_handle(pid, KernelObject::PipeWriter(id), Rights::WRITE.union(Rights::TRANSFER))?;
+        Ok((read, write))
+    })
+}
+
+pub fn read_pipe(handle: Handle, buf: &mut [u8]) -> Result<usize, HandleError> {
+    match lookup(handle, Rights::READ)? {
+        KernelObject::PipeReader(id) => Ok(pipe_read(id, buf)?),
+        _ => Err(HandleError::WrongType),
+    }
+}
+
+pub fn write_pipe(handle: Handle, data: &[u8]) -> Result<usize, HandleError> {
+    match lookup(handle, Rights::WRITE)? {
+        KernelObject::PipeWriter(id) => Ok(pipe_write(id, data)?),
+        _ => Err(HandleError::WrongType),
+    }
+}
+
+/*
+ * Stopped timer calling callback(arg) after period_ms, once or every
+ * period_ms. The callback is code of the caller, so the handle cannot be
+ * sent to another process, and the timer goes away when the caller exits.
+ * */
+pub fn open_timer(callback: TimerCallback, arg: *mut (), mode: TimerMode, period_ms: u32) -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let table = current_table()?;
+        if table.is_full() {
+            return Err(HandleError::TableFull);
+        }
+        let id = timer_create(callback, arg, mode, period_ms)?;
+        table.insert(KernelObject::Timer(id), Rights::READ.union(Rights::WRITE))
+    })
+}
+
+pub fn start_timer(handle: Handle) -> Result<(), HandleError> {
+    Ok(timer_start(timer_of(handle)?)?)
+}
+
+pub fn reset_timer(handle: Handle) -> Result<(), HandleError> {
+    Ok(timer_reset(timer_of(handle)?)?)
+}
+
+pub fn stop_timer(handle: Handle) -> Result<(), HandleError> {
+    Ok(timer_stop(timer_of(handle)?)?)
+}
+
+pub fn set_timer_period(handle: Handle, period_ms: u32) -> Result<(), HandleError> {
+    Ok(timer_set_period(timer_of(handle)?, period_ms)?)
+}
+
+/*
+ * How many times the timer expired since it was last started or read,
+ * poll() reports it READABLE until then
+ * */
+pub fn read_timer(handle: Handle) -> Result<u32, HandleError> {
+    Ok(timer_expirations(timer_of(handle)?, true)?)
+}
+
+/*
+ * Handle on the calling process's own incoming calls, poll() reports it
+ * READABLE while receive() would not block
+ * */
+pub fn open_endpoint() -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+        current_table()?.insert(KernelObject::Endpoint(pid), Rights::READ)
+    })
+}
+
+/*
+ * Map a shared memory handle, a handle without WRITE maps read only
+ * whatever the grant says
+ * */
+pub fn map_shm(handle: Handle) -> Result<ShmMapping, HandleError> {
+    let entry = cortex_m::interrupt::free(|_| current_table()?.check(handle, Rights::READ))?;
+    let id = match entry.object {
+        KernelObject::SharedMemory(id) => id,
+        _ => return Err(HandleError::WrongType),
+    };
+    let mut mapping = shm_map(id)?;
+    if !entry.rights.contains(Rights::WRITE) {
+        mapping.rights = ShmRights::ReadOnly;
+    }
+    Ok(mapping)
+}
+
+/*
+ * Copy of a handle with at most the rights of the original
+ * */
+pub fn dup(handle: Handle, rights: Rights) -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let table = current_table()?;
+        let entry = table.check(handle, rights)?;
+        let copy = table.insert(entry.object, rights)?;
+        retain_object(entry.object);
+        Ok(copy)
+    })
+}
+
+/*
+ * Give pid its own handle to the same object, needs TRANSFER and at
+ * least the rights being handed over. Returns the handle in pid's table.
+ * */
+pub fn send_handle(handle: Handle, pid: u8, rights: Rights) -> Result<Handle, HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let entry = current_table()?.check(handle, rights.union(Rights::TRANSFER))?;
+        let target = get_pcb(pid).ok_or(HandleError::NoProcess)?;
+        if target.handles.is_full() {
+            return Err(HandleError::TableFull);
+        }
+        if let KernelObject::SharedMemory(id) = entry.object {
+            shm_grant(id, pid, shm_rights(rights))?;
+        }
+        let handle = target.handles.insert(entry.object, rights)?;
+        retain_object(entry.object);
+        Ok(handle)
+    })
+}
+
+pub fn close(handle: Handle) -> Result<(), HandleError> {
+    cortex_m::interrupt::free(|_| {
+        let pid = unsafe { CURRENT }.ok_or(HandleError::NoCurrent)?;
+        let table = current_table()?;
+        let entry = table.remove(handle)?;
+        // The grant goes with the last handle on the region, a timer with the last one on it
+        if !table.refers_to(entry.object) {
+            match entry.object {
+                KernelObject::SharedMemory(id) => shm_release(id)?,
+                KernelObject::Timer(id) => timer_delete(id)?,
+                _ => {}
+            }
+        }
+        // Counted objects go with the last handle in any table
+        release_object(entry.object, pid);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::kernel;
+    use crate::{new_process, release_resources, ProcessBuilder, CONDVARS, EVENT_GROUPS, MAX_CONDVARS, MAX_EVENT_GROUPS,
+        MAX_MUTEXES, MAX_POOLS, MUTEXES, POOLS, PROCESS_MEMORY};
+    use core::ptr::addr_of;
+    use std::sync::MutexGuard;
+
+    fn body(_arg: *mut ()) -> ! {
+        loop {}
+    }
+
+    // A process to make the calls from
+    fn setup() -> (MutexGuard<'static, ()>, u8) {
+        let guard = kernel();
+        unsafe {
+            MUTEXES = [None; MAX_MUTEXES];
+            CONDVARS = [None; MAX_CONDVARS];
+            EVENT_GROUPS = [None; MAX_EVENT_GROUPS];
+            POOLS = [None; MAX_POOLS];
+        }
+        let pid = new_process(&ProcessBuilder::new(body)).unwrap();
+        unsafe { CURRENT = Some(pid) };
+        (guard, pid)
+    }
+
+    fn free_bytes() -> usize {
+        unsafe { (*addr_of!(PROCESS_MEMORY)).free_bytes() }
+    }
+
+    fn no_objects_left() -> bool {
+        unsafe {
+            (0..MAX_MUTEXES).all(|i| MUTEXES[i].is_none())
+                && (0..MAX_CONDVARS).all(|i| CONDVARS[i].is_none())
+                && (0..MAX_EVENT_GROUPS).all(|i| EVENT_GROUPS[i].is_none())
+                && (0..MAX_POOLS).all(|i| POOLS[i].is_none())
+        }
+    }
+
+    #[test]
+    fn close_destroys_counted_objects() {
+        let _kernel = setup();
+        let free = free_bytes();
+        // More rounds than any table has entries
+        for _ in 0..3 * MAX_MUTEXES {
+            close(open_mutex().unwrap()).unwrap();
+            close(open_condvar().unwrap()).unwrap();
+            close(open_event_group().unwrap()).unwrap();
+            close(open_pool(32, 4).unwrap()).unwrap();
+        }
+        assert!(no_objects_left());
+        assert_eq!(free_bytes(), free);
+    }
+
+    #[test]
+    fn object_outlives_all_but_last_handle() {
+        let _kernel = setup();
+        let group = open_event_group().unwrap();
+        let copy = dup(group, Rights::ALL).unwrap();
+        close(group).unwrap();
+        assert_eq!(set_events(copy, 0b101).unwrap(), 0b101);
+
+        close(copy).unwrap();
+        assert!(no_objects_left());
+    }
+
+    #[test]
+    fn pool_storage_waits_for_blocks() {
+        let (_kernel, pid) = setup();
+        let free = free_bytes();
+        let pool = open_pool(32, 4).unwrap();
+        block_alloc(pool).unwrap();
+        close(pool).unwrap();
+        // The block may still be in use
+        assert!(unsafe { POOLS[0].is_some() });
+
+        release_resources(pid);
+        assert!(no_objects_left());
+        assert_eq!(free_bytes(), free);
+    }
+
+    #[test]
+    fn exit_releases_handles() {
+        let (_kernel, pid) = setup();
+        open_mutex().unwrap();
+        open_condvar().unwrap();
+        open_event_group().unwrap();
+        open_pool(16, 2).unwrap();
+
+        release_resources(pid);
+        assert!(no_objects_left());
+    }
+}
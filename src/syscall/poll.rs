@@ -0,0 +1,121 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{block_current_until, current_table, deadline_after, deadline_passed, event_get, ipc_pending, pipe_poll,
+    pool_free_blocks, timer_expirations, unblock, yield_now, BlockReason, Handle, HandleError, KernelObject, ProcessState,
+    PROCS};
+
+/*
+ * What poll() watches a handle for, and what it found
+ * */
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PollEvents(u8);
+
+impl PollEvents {
+    pub const NONE: PollEvents = PollEvents(0);
+    pub const READABLE: PollEvents = PollEvents(1 << 0);    // Read, wait, alloc or receive would not block
+    pub const WRITABLE: PollEvents = PollEvents(1 << 1);    // Write would not block
+    pub const HANGUP: PollEvents = PollEvents(1 << 2);      // Other end closed, always reported
+
+    pub const fn union(self, other: PollEvents) -> PollEvents {
+        PollEvents(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: PollEvents) -> PollEvents {
+        PollEvents(self.0 & other.0)
+    }
+
+    pub const fn contains(self, other: PollEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PollFd {
+    pub handle: Handle,
+    pub events: PollEvents,     // Interest
+    pub mask: u32,              // Event group flags that count, all of them by default
+    pub revents: PollEvents,    // Filled in by poll()
+}
+
+impl PollFd {
+    pub const fn new(handle: Handle, events: PollEvents) -> Self {
+        Self { handle, events, mask: u32::MAX, revents: PollEvents::NONE }
+    }
+
+    // An event group is only readable with one of these flags set
+    pub const fn mask(mut self, mask: u32) -> Self {
+        self.mask = mask;
+        self
+    }
+}
+
+/*
+ * What object is ready for right now. An event group is readable with any
+ * flag of mask set, a timer once it expired since last started or read,
+ * an endpoint while a call waits to be received.
+ * */
+fn readiness(object: KernelObject, mask: u32) -> PollEvents {
+    match object {
+        KernelObject::PipeReader(_) | KernelObject::PipeWriter(_) => pipe_poll(object),
+        KernelObject::EventGroup(id) if event_get(id).is_ok_and(|bits| bits & mask != 0) => PollEvents::READABLE,
+        KernelObject::Pool(id) if pool_free_blocks(id).is_ok_and(|n| n > 0) => PollEvents::READABLE,
+        KernelObject::Timer(id) if timer_expirations(id, false).is_ok_and(|n| n > 0) => PollEvents::READABLE,
+        KernelObject::Endpoint(pid) if ipc_pending(pid) => PollEvents::READABLE,
+        _ => PollEvents::NONE,
+    }
+}
+
+/*
+ * Wait until one of fds is ready for what it asks, or timeout_ms passes.
+ * Sets revents on every entry and returns how many are ready, 0 on timeout.
+ * */
+pub fn poll(fds: &mut [PollFd], timeout_ms: Option<u32>) -> Result<usize, HandleError> {
+    let deadline = deadline_after(timeout_ms);
+
+    loop {
+        let ready = cortex_m::interrupt::free(|_| {
+            let table = current_table()?;
+            let mut ready = 0;
+            for fd in fds.iter_mut() {
+                let object = table.get(fd.handle)?.object;
+                fd.revents = readiness(object, fd.mask).intersection(fd.events.union(PollEvents::HANGUP));
+                if !fd.revents.is_empty() {
+                    ready += 1;
+                }
+            }
+            if ready > 0 || deadline_passed(deadline) {
+                return Ok(Some(ready));
+            }
+
+            // One block for all of them, whichever object changes first wakes us
+            block_current_until(BlockReason::Polling, deadline)?;
+            Ok::<_, HandleError>(None)
+        })?;
+
+        if let Some(ready) = ready {
+            return Ok(ready);
+        }
+        yield_now()?;
+    }
+}
+
+/*
+ * object changed state, wake the pollers that hold a handle to it so
+ * they look again
+ * */
+pub(crate) fn wake_pollers(object: KernelObject) {
+    unsafe {
+        for pid in 0..MAX_PROCS as u8 {
+            let polling = matches!(PROCS[pid as usize].as_ref(),
+                Some(pcb) if matches!(pcb.state, ProcessState::Blocked(BlockReason::Polling))
+                    && pcb.handles.refers_to(object));
+            if polling {
+                let _ = unblock(pid);
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{get_pcb, ProcessError, CURRENT, PROCS};
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapUsage {
+    pub used: usize,
+    pub peak: usize,
+    pub limit: usize,
+}
+
+/*
+ * Move the break of the calling process heap by increment bytes,
+ * returns the previous break. The heap can never grow past the limit
+ * chosen at creation, so a leaking process only starves itself.
+ * */
+pub fn sbrk(increment: isize) -> Result<*mut u8, ProcessError> {
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(ProcessError::NoMemory)?;
+        let pcb = PROCS[pid as usize].as_mut().ok_or(ProcessError::NoMemory)?;
+
+        let old = pcb.heap_used;
+        let new = old
+            .checked_add_signed(increment)
+            .filter(|&new| new <= pcb.heap_limit)
+            .ok_or(ProcessError::HeapLimit)?;
+
+        pcb.heap_used = new;
+        pcb.heap_peak = pcb.heap_peak.max(new);
+        Ok(pcb.heap_base.add(old))
+    })
+}
+
+pub fn heap_usage(pid: u8) -> Option<HeapUsage> {
+    cortex_m::interrupt::free(|_| {
+        get_pcb(pid).map(|pcb| HeapUsage {
+            used: pcb.heap_used,
+            peak: pcb.heap_peak,
+            limit: pcb.heap_limit,
+        })
+    })
+}
@@ -0,0 +1,238 @@
+use crate::{get_pcb, has_capability, kill_with, exit_with, yield_now, Capabilities, ExitReason, ProcessState,
+    SchedulerError, CURRENT, IDLE};
+use core::ptr;
+
+/*
+ * Asynchronous notifications. A signal sent to a process is left pending
+ * in its PCB, and the next time the process is switched in the kernel
+ * builds a frame on its stack that runs the handler, then sigreturn()
+ * puts the interrupted context back. Without a handler most signals
+ * terminate the process.
+ * */
+
+pub const NSIG: usize = 8;
+
+pub const SIGHUP: u8 = 1;
+pub const SIGINT: u8 = 2;
+pub const SIGKILL: u8 = 3;     // Cannot be caught, ignored or blocked
+pub const SIGUSR1: u8 = 4;
+pub const SIGUSR2: u8 = 5;
+pub const SIGTERM: u8 = 6;
+pub const SIGCHLD: u8 = 7;     // Ignored unless a handler is set
+
+pub type SignalHandler = fn(u8);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalError {
+    InvalidSignal,
+    CannotCatch,        // SIGKILL always takes its default action
+    NoProcess,
+    PermissionDenied,   // Signalling another process needs Capabilities::KILL
+    NoCurrent,
+}
+
+impl From<SchedulerError> for SignalError {
+    fn from(e: SchedulerError) -> Self {
+        match e {
+            SchedulerError::PermissionDenied => SignalError::PermissionDenied,
+            SchedulerError::NoCurrent => SignalError::NoCurrent,
+            _ => SignalError::NoProcess,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SignalAction {
+    Default,
+    Ignore,
+    Handler(SignalHandler),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SigMask {
+    Block,      // Add to the blocked set
+    Unblock,    // Remove from it
+    SetMask,    // Replace it
+}
+
+/*
+ * Per process signal state, bit n of the masks is signal n
+ * */
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalState {
+    pub pending: u8,
+    pub blocked: u8,
+    pub ignored: u8,
+    pub handlers: [Option<SignalHandler>; NSIG],
+    pub returning: *mut u32,    // Record passed to sigreturn, picked up by the context switch
+}
+
+impl SignalState {
+    pub const EMPTY: SignalState = SignalState {
+        pending: 0,
+        blocked: 0,
+        ignored: 1 << SIGCHLD,
+        handlers: [None; NSIG],
+        returning: ptr::null_mut(),
+    };
+
+    fn handled(&self) -> u8 {
+        (1..NSIG).filter(|&s| self.handlers[s].is_some()).fold(0, |m, s| m | 1 << s)
+    }
+
+    // Pending signals the process is ready to take through a handler
+    pub fn deliverable(&self) -> u8 {
+        self.pending & !self.blocked & self.handled()
+    }
+
+    // Pending signals that terminate the process once unblocked
+    fn fatal(&self) -> u8 {
+        self.pending & !self.blocked & !self.handled() & !self.ignored
+    }
+}
+
+fn check_signal(sig: u8) -> Result<(), SignalError> {
+    match sig as usize {
+        1..NSIG => Ok(()),
+        _ => Err(SignalError::InvalidSignal),
+    }
+}
+
+/*
+ * Set what the calling process does on sig, returns the previous action
+ * */
+pub fn sigaction(sig: u8, action: SignalAction) -> Result<SignalAction, SignalError> {
+    check_signal(sig)?;
+    if sig == SIGKILL {
+        return Err(SignalError::CannotCatch);
+    }
+    cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SignalError::NoCurrent)?;
+        let signals = &mut get_pcb(pid).ok_or(SignalError::NoProcess)?.signals;
+        let bit = 1 << sig;
+
+        let old = match signals.handlers[sig as usize] {
+            Some(handler) => SignalAction::Handler(handler),
+            None if signals.ignored & bit != 0 => SignalAction::Ignore,
+            None => SignalAction::Default,
+        };
+
+        signals.handlers[sig as usize] = None;
+        signals.ignored &= !bit;
+        match action {
+            SignalAction::Handler(handler) => signals.handlers[sig as usize] = Some(handler),
+            SignalAction::Ignore => {
+                signals.ignored |= bit;
+                signals.pending &= !bit;
+            }
+            SignalAction::Default if sig == SIGCHLD => signals.ignored |= bit,
+            SignalAction::Default => {}
+        }
+        Ok(old)
+    })
+}
+
+/*
+ * Change the blocked set of the calling process, returns the old one.
+ * Signals unblocked here that were pending are taken before returning.
+ * */
+pub fn sigprocmask(how: SigMask, mask: u8) -> Result<u8, SignalError> {
+    let (old, fatal, deliver) = cortex_m::interrupt::free(|_| unsafe {
+        let pid = CURRENT.ok_or(SignalError::NoCurrent)?;
+        let signals = &mut get_pcb(pid).ok_or(SignalError::NoProcess)?.signals;
+        let old = signals.blocked;
+
+        signals.blocked = match how {
+            SigMask::Block => old | mask,
+            SigMask::Unblock => old & !mask,
+            SigMask::SetMask => mask,
+        } & !(1 << SIGKILL | 1);
+        Ok::<_, SignalError>((old, signals.fatal(), signals.deliverable() != 0))
+    })?;
+
+    if fatal != 0 {
+        exit_with(ExitReason::Signaled(fatal.trailing_zeros() as u8));
+    }
+    // Handlers run on the way back in from the context switch
+    if deliver {
+        yield_now()?;
+    }
+    Ok(old)
+}
+
+/*
+ * Send sig to pid. Caught signals run the handler the next time pid is
+ * switched in, blocked ones wait until unblocked. Any other process than
+ * the caller needs Capabilities::KILL.
+ * */
+pub fn send_signal(pid: u8, sig: u8) -> Result<(), SignalError> {
+    check_signal(sig)?;
+    let current = unsafe { CURRENT };
+    if current != Some(pid) && !has_capability(Capabilities::KILL) {
+        return Err(SignalError::PermissionDenied);
+    }
+
+    let terminate = cortex_m::interrupt::free(|_| unsafe {
+        if IDLE == Some(pid) {
+            return Err(SignalError::NoProcess);
+        }
+        let pcb = get_pcb(pid).ok_or(SignalError::NoProcess)?;
+        if matches!(pcb.state, ProcessState::Zombie(_)) {
+            return Err(SignalError::NoProcess);
+        }
+
+        let signals = &mut pcb.signals;
+        let bit = 1 << sig;
+        if sig == SIGKILL {
+            return Ok(true);
+        }
+        if signals.handlers[sig as usize].is_none() && signals.ignored & bit != 0 {
+            return Ok(false);
+        }
+        signals.pending |= bit;
+        Ok(signals.fatal() & bit != 0)
+    })?;
+
+    if terminate {
+        if current == Some(pid) {
+            exit_with(ExitReason::Signaled(sig));
+        }
+        kill_with(pid, ExitReason::Signaled(sig))?;
+    } else if current == Some(pid) {
+        // Let our own handler run before carrying on
+        yield_now()?;
+    }
+    Ok(())
+}
+
+/*
+ * Entered from the frame built by deliver_signal, with the handler
+ * address in r1 and the record that describes the interrupted context in r2
+ * */
+#[cfg(target_arch = "arm")]
+pub(crate) extern "C" fn signal_trampoline(sig: u32, handler: usize, record: *mut u32) -> ! {
+    let handler: SignalHandler = unsafe { core::mem::transmute(handler) };
+    handler(sig as u8);
+    sigreturn(record)
+}
+
+/*
+ * Leave a handler. The context switch this yields into resumes the
+ * process where the signal interrupted it
+ * */
+#[cfg(target_arch = "arm")]
+fn sigreturn(record: *mut u32) -> ! {
+    cortex_m::interrupt::free(|_| unsafe {
+        if let Some(pcb) = CURRENT.and_then(get_pcb) {
+            pcb.signals.returning = record;
+        }
+    });
+
+    let _ = yield_now();
+
+    // Only reached if the record was refused, the switch faults us instead
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -1,8 +1,13 @@
-use crate::{get_time_us, yield_now, BlockReason, Scheduler, SchedulerError, SleepEntry, CURRENT, PROCS, SLEEP_QUEUE};
+use crate::{get_pcb, get_time_us, has_capability, take_wake_value, Capabilities, unblock_with, yield_now, BlockReason, ProcessState, Scheduler,
+    SchedulerError, SleepEntry, CURRENT, PROCS, SLEEP_QUEUE};
 
+/*
+ * Block for ms milliseconds. Returns Interrupted if wake() ended the
+ * sleep early.
+ * */
 pub fn sleep_ms(ms: u32) -> Result<(), SchedulerError> {
     let wake_time = get_time_us() + (ms as u64 * 1000);
-    unsafe {
+    cortex_m::interrupt::free(|_| unsafe {
         let pid = CURRENT.ok_or(SchedulerError::NoCurrent)?;
         let entry = SleepEntry{
             pid: pid, 
@@ -12,11 +17,38 @@ pub fn sleep_ms(ms: u32) -> Result<(), SchedulerError> {
         let q = core::ptr::addr_of_mut!(SLEEP_QUEUE);
         (*q).enqueue(entry)?;
 
+        // Only wake() leaves a value for a sleeper, drop anything older
+        let _ = take_wake_value();
         PROCS[pid as usize].as_mut().unwrap().state =
-            crate::ProcessState::Blocked(BlockReason::Sleeping(wake_time));
+            ProcessState::Blocked(BlockReason::Sleeping(wake_time));
+        Ok(())
+    })?;
 
-        yield_now()?;
+    yield_now()?;
+
+    match cortex_m::interrupt::free(|_| take_wake_value()) {
+        Some(_) => Err(SchedulerError::Interrupted),
+        None => Ok(()),
     }
+}
 
-    Ok(())
+/*
+ * End the sleep of pid early, its sleep_ms returns Interrupted. Only the
+ * parent of pid may, or a caller with Capabilities::KILL. A process
+ * waiting out its restart backoff is not sleeping and cannot be woken.
+ * */
+pub fn wake(pid: u8) -> Result<(), SchedulerError> {
+    cortex_m::interrupt::free(|_| {
+        let pcb = get_pcb(pid).ok_or(SchedulerError::ProcessNotFound)?;
+        let current = unsafe { CURRENT };
+        let related = current.is_some() && (current == Some(pid) || pcb.parent == current);
+        if !related && !has_capability(Capabilities::KILL) {
+            return Err(SchedulerError::PermissionDenied);
+        }
+        if !matches!(pcb.state, ProcessState::Blocked(BlockReason::Sleeping(_))) {
+            return Err(SchedulerError::NotRunnable);
+        }
+        // unblock takes it off the sleep queue
+        unblock_with(pid, 0)
+    })
 }
@@ -0,0 +1,29 @@
+use crate::{block_current, get_pcb, reap, yield_now, BlockReason, ExitReason, ProcessState, SchedulerError, CURRENT};
+
+/*
+ * Block until child pid exits, then reap it and return how it ended.
+ * Only the parent can wait on a process.
+ * */
+pub fn wait(pid: u8) -> Result<ExitReason, SchedulerError> {
+    loop {
+        let done = cortex_m::interrupt::free(|_| unsafe {
+            let me = CURRENT.ok_or(SchedulerError::NoCurrent)?;
+            let child = get_pcb(pid)
+                .filter(|child| child.parent == Some(me))
+                .ok_or(SchedulerError::ProcessNotFound)?;
+
+            if let ProcessState::Zombie(reason) = child.state {
+                reap(pid);
+                return Ok(Some(reason));
+            }
+
+            block_current(BlockReason::WaitingForChild(pid))?;
+            Ok(None)
+        })?;
+
+        if let Some(reason) = done {
+            return Ok(reason);
+        }
+        yield_now()?;
+    }
+}
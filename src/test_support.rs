@@ -0,0 +1,72 @@
+use crate::scheduler::MAX_PROCS;
+use crate::{MemoryRegion, RegionAllocator, CURRENT, HANDOFF, IDLE, PROCESS_MEMORY, PROCS, RR, SCHEDULER,
+    SLEEP_QUEUE};
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/*
+ * Host stand-ins for what the kernel gets from the RP2040: the cortex-m
+ * primitives interrupt::free links against, a clock the tests move by
+ * hand and a processes region in host memory. Tests that touch the
+ * kernel statics hold kernel(), so they run one at a time.
+ * */
+
+#[unsafe(no_mangle)]
+extern "C" fn __cpsid() {}
+
+#[unsafe(no_mangle)]
+extern "C" fn __cpsie() {}
+
+#[unsafe(no_mangle)]
+extern "C" fn __wfi() {}
+
+// PRIMASK clear, interrupts were on
+#[unsafe(no_mangle)]
+extern "C" fn __primask_r() -> u32 { 0 }
+
+// memory.x symbols MemoryLayout::new reads, never used since the processes region is set up below
+macro_rules! layout_symbols {
+    ($($name:ident),*) => { $( #[unsafe(no_mangle)] static $name: u8 = 0; )* };
+}
+
+layout_symbols!(_kernel_data_start, _kernel_data_size, _kernel_heap_start, _kernel_heap_size, _wifi_start, _wifi_size,
+    _processes_start, _processes_size, _apps_start, _apps_size, _core0_stack_start, _core0_stack_size,
+    _core1_stack_start, _core1_stack_size);
+
+static NOW_US: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn now_us() -> u64 {
+    NOW_US.load(Ordering::Relaxed)
+}
+
+pub(crate) fn advance_ms(ms: u64) {
+    NOW_US.fetch_add(ms * 1000, Ordering::Relaxed);
+}
+
+const ARENA_SIZE: usize = 64 * 1024;
+static mut ARENA: [u64; ARENA_SIZE / 8] = [0; ARENA_SIZE / 8];
+
+static KERNEL: Mutex<()> = Mutex::new(());
+
+/*
+ * Exclusive use of the kernel statics, reset to how boot leaves them
+ * */
+pub(crate) fn kernel() -> MutexGuard<'static, ()> {
+    // A failed test only poisons the lock, the reset below cleans up after it
+    let guard = KERNEL.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe {
+        for i in 0..MAX_PROCS {
+            PROCS[i] = None;
+        }
+        CURRENT = None;
+        IDLE = None;
+        HANDOFF = None;
+        SCHEDULER = RR::new();
+        SLEEP_QUEUE = Default::default();
+        PROCESS_MEMORY = RegionAllocator::new();
+        (*addr_of_mut!(PROCESS_MEMORY)).init(MemoryRegion { start: addr_of_mut!(ARENA) as usize, size: ARENA_SIZE });
+    }
+    guard
+}
+
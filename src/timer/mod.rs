@@ -0,0 +1,5 @@
+pub mod queue;
+pub mod service;
+
+pub use queue::*;
+pub use service::*;
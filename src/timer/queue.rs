@@ -0,0 +1,115 @@
+use crate::{TimerError, MAX_TIMERS};
+
+/*
+ * Armed timer, keyed by expiry time (us)
+ * */
+#[derive(Clone, Copy)]
+pub struct TimerEntry {
+    pub id: u8,
+    pub expires: u64,
+}
+
+const DUMMY: TimerEntry = TimerEntry { id: 0, expires: u64::MAX };
+
+/*
+ * Min-heap of armed timers, same layout as SleepQueue. Each timer is in
+ * it at most once, stopping one takes it out instead of leaving it stale.
+ * */
+pub struct TimerHeap {
+    heap: [TimerEntry; MAX_TIMERS],
+    size: usize,
+}
+
+impl TimerHeap {
+    pub const fn new() -> Self {
+        Self { heap: [DUMMY; MAX_TIMERS], size: 0 }
+    }
+
+    fn bubble_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) >> 1;
+            if self.heap[parent].expires <= self.heap[i].expires {
+                break;
+            }
+            self.heap.swap(parent, i);
+            i = parent;
+        }
+    }
+
+    fn bubble_down(&mut self, mut i: usize) {
+        loop {
+            let left = 2 * i + 1;
+            let right = 2 * i + 2;
+            let mut smallest = i;
+
+            if left < self.size && self.heap[left].expires < self.heap[smallest].expires {
+                smallest = left;
+            }
+            if right < self.size && self.heap[right].expires < self.heap[smallest].expires {
+                smallest = right;
+            }
+            if smallest == i {
+                break;
+            }
+            self.heap.swap(i, smallest);
+            i = smallest;
+        }
+    }
+
+    pub fn push(&mut self, entry: TimerEntry) -> Result<(), TimerError> {
+        if self.size == MAX_TIMERS {
+            return Err(TimerError::QueueFull);
+        }
+        self.heap[self.size] = entry;
+        self.size += 1;
+        self.bubble_up(self.size - 1);
+        Ok(())
+    }
+
+    // Earliest armed timer
+    pub fn peek(&self) -> Option<TimerEntry> {
+        (self.size > 0).then_some(self.heap[0])
+    }
+
+    /*
+     * Earliest timer, if it expired by now
+     * */
+    pub fn pop_expired(&mut self, now: u64) -> Option<TimerEntry> {
+        let first = self.peek().filter(|e| e.expires <= now)?;
+        self.take(0);
+        Some(first)
+    }
+
+    /*
+     * Disarm timer id, false if it was not armed
+     * */
+    pub fn remove(&mut self, id: u8) -> bool {
+        match (0..self.size).find(|&i| self.heap[i].id == id) {
+            Some(i) => {
+                self.take(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Fill the hole at i with the last entry, which may have to go either way
+    fn take(&mut self, i: usize) {
+        self.size -= 1;
+        self.heap[i] = self.heap[self.size];
+        if i < self.size {
+            self.bubble_up(i);
+            self.bubble_down(i);
+        }
+    }
+
+    pub fn len(&self) -> usize { self.size }
+
+    pub fn is_empty(&self) -> bool { self.size == 0 }
+}
+
+impl Default for TimerHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
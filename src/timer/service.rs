@@ -0,0 +1,400 @@
+use crate::{block_current, get_pcb, get_time_us, switch_to, unblock, wake_pollers, yield_now, BlockReason,
+    KernelObject, ProcessBuilder, ProcessError, ProcessState, RestartPolicy, RestartSpec, TimerEntry, TimerHeap, CURRENT};
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+use rp2040_hal::pac::interrupt;
+use rp2040_hal::timer::{Alarm, Alarm1, Instant};
+
+/*
+ * Software timers. Armed timers sit in a min-heap, Alarm1 fires at the
+ * earliest one and hands the CPU straight to the timer task, which runs
+ * the callbacks of everything that expired. Callbacks share that task's
+ * stack, keep them short and never block in them.
+ * */
+
+pub const MAX_TIMERS: usize = 16;
+const TIMER_STACK_SIZE: usize = 1024;
+
+// A callback that faults takes the task down, bring it back
+const TIMER_RESTART: RestartSpec = RestartSpec::new(RestartPolicy::OnFailure, 3, 1000, 10);
+
+pub type TimerCallback = fn(*mut ());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimerMode {
+    OneShot,
+    Periodic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimerError {
+    NoTimer,
+    TooManyTimers,
+    InvalidPeriod,
+    QueueFull,
+    NotOwner,       // Timers can only be changed by the process that created them
+    NoService,      // start_timer_service was not called
+}
+
+#[derive(Clone, Copy)]
+pub struct SoftTimer {
+    callback: TimerCallback,
+    arg: *mut (),
+    mode: TimerMode,
+    period_us: u64,
+    running: bool,
+    expirations: u32,       // Since it was last started or read, what poll() looks at
+    owner: Option<u8>,      // None for timers created by the kernel
+}
+
+impl SoftTimer {
+    pub fn is_running(&self) -> bool { self.running }
+
+    pub fn mode(&self) -> TimerMode { self.mode }
+
+    pub fn period_us(&self) -> u64 { self.period_us }
+}
+
+pub static mut SOFT_TIMERS: [Option<SoftTimer>; MAX_TIMERS] = [None; MAX_TIMERS];
+static mut TIMER_QUEUE: TimerHeap = TimerHeap::new();
+static mut TIMER_TASK: Option<u8> = None;
+
+static TIMER_ALARM: Mutex<RefCell<Option<Alarm1>>> = Mutex::new(RefCell::new(None));
+
+fn queue() -> &'static mut TimerHeap {
+    unsafe { &mut *core::ptr::addr_of_mut!(TIMER_QUEUE) }
+}
+
+/*
+ * Timer id, if the caller may change it
+ * */
+fn get_timer(id: u8) -> Result<&'static mut SoftTimer, TimerError> {
+    if id as usize >= MAX_TIMERS {
+        return Err(TimerError::NoTimer);
+    }
+    let timer = unsafe { SOFT_TIMERS[id as usize].as_mut() }.ok_or(TimerError::NoTimer)?;
+    match unsafe { CURRENT } {
+        // The kernel and the callbacks in the timer task may touch any timer
+        Some(pid) if timer.owner != Some(pid) && unsafe { TIMER_TASK } != Some(pid) => Err(TimerError::NotOwner),
+        _ => Ok(timer),
+    }
+}
+
+/*
+ * Point Alarm1 at the earliest armed timer. An expiry already in the past
+ * raises the interrupt right away.
+ * */
+fn program_alarm() {
+    cortex_m::interrupt::free(|cs| {
+        let mut alarm = TIMER_ALARM.borrow(cs).borrow_mut();
+        let alarm = match alarm.as_mut() {
+            Some(alarm) => alarm,
+            None => return,
+        };
+        match queue().peek() {
+            Some(first) => {
+                // The alarm only reaches u32::MAX us ahead, it fires early and we look again
+                let latest = get_time_us() + u32::MAX as u64;
+                let _ = alarm.schedule_at(Instant::from_ticks(first.expires.min(latest)));
+            }
+            None => {
+                let _ = alarm.cancel();
+            }
+        }
+    });
+}
+
+/*
+ * Start the timer task and give it Alarm1. Call once at boot, before
+ * start_first_process, then unmask TIMER_IRQ_1.
+ * */
+pub fn start_timer_service(mut alarm: Alarm1) -> Result<u8, ProcessError> {
+    alarm.enable_interrupt();
+    cortex_m::interrupt::free(|cs| {
+        TIMER_ALARM.borrow(cs).replace(Some(alarm));
+    });
+
+    spawn_timer_task()
+}
+
+/*
+ * The task runs once to arm the alarm and block, from then on only the
+ * alarm interrupt wakes it
+ * */
+fn spawn_timer_task() -> Result<u8, ProcessError> {
+    let pid = ProcessBuilder::new(timer_task)
+        .name("timers")
+        .stack_size(TIMER_STACK_SIZE)
+        .restart(TIMER_RESTART)
+        .spawn()?;
+    unsafe { TIMER_TASK = Some(pid) };
+    Ok(pid)
+}
+
+/*
+ * Create a stopped timer calling callback(arg) after period_ms, once or
+ * every period_ms depending on mode
+ * */
+pub(crate) fn timer_create(callback: TimerCallback, arg: *mut (), mode: TimerMode,
+    period_ms: u32) -> Result<u8, TimerError> {
+    if period_ms == 0 {
+        return Err(TimerError::InvalidPeriod);
+    }
+    cortex_m::interrupt::free(|_| unsafe {
+        TIMER_TASK.ok_or(TimerError::NoService)?;
+        let id = (0..MAX_TIMERS)
+            .find(|&i| SOFT_TIMERS[i].is_none())
+            .ok_or(TimerError::TooManyTimers)?;
+        SOFT_TIMERS[id] = Some(SoftTimer {
+            callback,
+            arg,
+            mode,
+            period_us: period_ms as u64 * 1000,
+            running: false,
+            expirations: 0,
+            owner: CURRENT,
+        });
+        Ok(id as u8)
+    })
+}
+
+/*
+ * Arm the timer one period from now. A running timer starts its period
+ * over, same as timer_reset.
+ * */
+pub(crate) fn timer_start(id: u8) -> Result<(), TimerError> {
+    cortex_m::interrupt::free(|_| {
+        let timer = get_timer(id)?;
+        queue().remove(id);
+        queue().push(TimerEntry { id, expires: get_time_us() + timer.period_us })?;
+        timer.running = true;
+        timer.expirations = 0;
+        program_alarm();
+        Ok(())
+    })
+}
+
+pub(crate) fn timer_reset(id: u8) -> Result<(), TimerError> {
+    timer_start(id)
+}
+
+/*
+ * Disarm the timer, a callback already running is not interrupted
+ * */
+pub(crate) fn timer_stop(id: u8) -> Result<(), TimerError> {
+    cortex_m::interrupt::free(|_| {
+        let timer = get_timer(id)?;
+        timer.running = false;
+        if queue().remove(id) {
+            program_alarm();
+        }
+        Ok(())
+    })
+}
+
+/*
+ * Change the period, a running timer is rearmed with the new one
+ * */
+pub(crate) fn timer_set_period(id: u8, period_ms: u32) -> Result<(), TimerError> {
+    if period_ms == 0 {
+        return Err(TimerError::InvalidPeriod);
+    }
+    let running = cortex_m::interrupt::free(|_| {
+        let timer = get_timer(id)?;
+        timer.period_us = period_ms as u64 * 1000;
+        Ok::<_, TimerError>(timer.running)
+    })?;
+    if running {
+        timer_start(id)?;
+    }
+    Ok(())
+}
+
+/*
+ * Expirations since the timer was last started or read, read clears them
+ * */
+pub(crate) fn timer_expirations(id: u8, read: bool) -> Result<u32, TimerError> {
+    cortex_m::interrupt::free(|_| {
+        let timer = get_timer(id)?;
+        let count = timer.expirations;
+        if read {
+            timer.expirations = 0;
+        }
+        Ok(count)
+    })
+}
+
+pub(crate) fn timer_delete(id: u8) -> Result<(), TimerError> {
+    timer_stop(id)?;
+    cortex_m::interrupt::free(|_| unsafe { SOFT_TIMERS[id as usize] = None });
+    Ok(())
+}
+
+/*
+ * Next expired timer to run, periodic ones are rearmed here
+ * */
+fn next_expired() -> Option<(TimerCallback, *mut ())> {
+    let now = get_time_us();
+    loop {
+        let entry = queue().pop_expired(now)?;
+        let timer = match unsafe { SOFT_TIMERS[entry.id as usize].as_mut() } {
+            Some(timer) => timer,
+            None => continue,
+        };
+
+        timer.expirations = timer.expirations.saturating_add(1);
+        wake_pollers(KernelObject::Timer(entry.id));
+
+        match timer.mode {
+            TimerMode::OneShot => timer.running = false,
+            TimerMode::Periodic => {
+                // Keep the period from drifting, but skip what was missed instead of bursting
+                let mut expires = entry.expires + timer.period_us;
+                if expires <= now {
+                    expires = now + timer.period_us;
+                }
+                let _ = queue().push(TimerEntry { id: entry.id, expires });
+            }
+        }
+        return Some((timer.callback, timer.arg));
+    }
+}
+
+/*
+ * Callbacks of everything expired, run with interrupts on, one at a time
+ * */
+fn run_expired() {
+    while let Some((callback, arg)) = cortex_m::interrupt::free(|_| next_expired()) {
+        callback(arg);
+    }
+}
+
+/*
+ * Kernel task running the callbacks, woken by the alarm
+ * */
+fn timer_task(_arg: *mut ()) -> ! {
+    loop {
+        run_expired();
+
+        // An expiry that slips in before we block raises the alarm, which unblocks us
+        cortex_m::interrupt::free(|_| {
+            program_alarm();
+            let _ = block_current(BlockReason::WaitingForTimer);
+        });
+        let _ = yield_now();
+    }
+}
+
+/*
+ * Drop the timers of a process that stopped running, their callbacks
+ * point into its code
+ * */
+pub(crate) fn release_timers(pid: u8) {
+    let mut changed = false;
+    let timers = unsafe { &mut *core::ptr::addr_of_mut!(SOFT_TIMERS) };
+    for (id, timer) in timers.iter_mut().enumerate() {
+        if timer.is_some_and(|t| t.owner == Some(pid)) {
+            *timer = None;
+            changed |= queue().remove(id as u8);
+        }
+    }
+    if changed {
+        program_alarm();
+    }
+}
+
+#[interrupt]
+fn TIMER_IRQ_1() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(alarm) = TIMER_ALARM.borrow(cs).borrow_mut().as_mut() {
+            alarm.clear_interrupt();
+        }
+    });
+
+    // The timer task goes ahead of the run queue
+    let waiting = unsafe { TIMER_TASK }.and_then(get_pcb).is_some_and(|pcb|
+        matches!(pcb.state, ProcessState::Blocked(BlockReason::WaitingForTimer)));
+    if let Some(pid) = unsafe { TIMER_TASK }.filter(|_| waiting) {
+        let _ = unblock(pid);
+        let _ = switch_to(pid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{advance_ms, kernel};
+    use crate::{Scheduler, SCHEDULER};
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::MutexGuard;
+
+    fn setup() -> MutexGuard<'static, ()> {
+        let guard = kernel();
+        unsafe {
+            SOFT_TIMERS = [None; MAX_TIMERS];
+            TIMER_QUEUE = TimerHeap::new();
+            TIMER_TASK = None;
+        }
+        spawn_timer_task().unwrap();
+        guard
+    }
+
+    fn count(arg: *mut ()) {
+        unsafe { (*(arg as *const AtomicU32)).fetch_add(1, Ordering::Relaxed) };
+    }
+
+    fn counting_timer(fired: &AtomicU32, mode: TimerMode, period_ms: u32) -> u8 {
+        timer_create(count, fired as *const AtomicU32 as *mut (), mode, period_ms).unwrap()
+    }
+
+    #[test]
+    fn timer_task_is_queued_to_run() {
+        let _kernel = setup();
+        let pid = unsafe { TIMER_TASK }.unwrap();
+        assert!(matches!(get_pcb(pid).unwrap().state, ProcessState::Ready));
+        let sched = unsafe { &mut *core::ptr::addr_of_mut!(SCHEDULER) };
+        assert_eq!(sched.dequeue().unwrap(), pid);
+    }
+
+    #[test]
+    fn one_shot_fires_once() {
+        let _kernel = setup();
+        let fired = AtomicU32::new(0);
+        let id = counting_timer(&fired, TimerMode::OneShot, 5);
+        timer_start(id).unwrap();
+
+        advance_ms(4);
+        run_expired();
+        assert_eq!(fired.load(Ordering::Relaxed), 0);
+
+        advance_ms(1);
+        run_expired();
+        assert_eq!(fired.load(Ordering::Relaxed), 1);
+
+        advance_ms(20);
+        run_expired();
+        assert_eq!(fired.load(Ordering::Relaxed), 1);
+        assert!(!get_timer(id).unwrap().is_running());
+    }
+
+    #[test]
+    fn periodic_fires_every_period() {
+        let _kernel = setup();
+        let fired = AtomicU32::new(0);
+        let id = counting_timer(&fired, TimerMode::Periodic, 10);
+        timer_start(id).unwrap();
+
+        for n in 1..=3 {
+            advance_ms(10);
+            run_expired();
+            assert_eq!(fired.load(Ordering::Relaxed), n);
+        }
+        assert_eq!(timer_expirations(id, true).unwrap(), 3);
+        assert_eq!(timer_expirations(id, false).unwrap(), 0);
+
+        timer_stop(id).unwrap();
+        advance_ms(50);
+        run_expired();
+        assert_eq!(fired.load(Ordering::Relaxed), 3);
+    }
+}